@@ -0,0 +1,231 @@
+//! Fault injection for testing how allocation failures are handled.
+//!
+//! When the `fault_injection` feature is enabled, allocations made by `mbox` on the current thread
+//! (e.g. through `MBox::new()`, `MVec::try_reserve()` or `gen_realloc()`) can be made to fail on
+//! demand. An injected failure behaves exactly like `malloc()` returning NULL: the `try_*`
+//! functions return an `AllocError`, and the infallible ones call `handle_alloc_error()`.
+//!
+//! ```rust
+//! extern crate mbox;
+//!
+//! use mbox::fault::{self, Fault};
+//! use mbox::MBox;
+//!
+//! let guard = fault::inject(Fault::Nth(1));
+//! assert!(MBox::try_new(1_u32).is_ok());
+//! assert!(MBox::try_new(2_u32).is_err());
+//! assert!(MBox::try_new(3_u32).is_ok());
+//! assert_eq!(fault::failures(), 1);
+//! drop(guard);
+//! ```
+//!
+//! Faults only affect the thread which injected them, so tests running in parallel do not
+//! interfere with each other. The global allocator (including `mbox::Malloc`) is never affected.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+/// How allocations on the current thread should fail.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Fault {
+    /// Fails only the `n`-th allocation (counting from 0) made after the fault is injected.
+    Nth(usize),
+    /// Fails every allocation requesting more than this number of bytes.
+    Above(usize),
+    /// Fails each allocation with the given probability (between 0.0 and 1.0). The failures are
+    /// chosen by a pseudo-random generator initialized from `seed`, so the same seed always fails
+    /// the same allocations.
+    Random {
+        /// The seed of the pseudo-random generator.
+        seed: u64,
+        /// The probability that an allocation fails.
+        probability: f64,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+struct State {
+    fault: Fault,
+    allocations: usize,
+    failures: usize,
+    rng: u64,
+}
+
+thread_local! {
+    static STATE: Cell<Option<State>> = Cell::new(None);
+}
+
+/// Restores the previously injected fault (if any) of the current thread when dropped.
+#[must_use = "the fault is removed immediately if the guard is not kept alive"]
+#[derive(Debug)]
+pub struct FaultGuard {
+    previous: Option<State>,
+    // the guard restores a thread-local state, so it must not be moved to another thread.
+    _marker: PhantomData<*const ()>,
+}
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        STATE.with(|state| state.set(self.previous));
+    }
+}
+
+/// Injects a fault into the allocations made by `mbox` on the current thread, until the returned
+/// guard is dropped.
+///
+/// Injecting a new fault while another one is active replaces it, and the old fault is restored
+/// when the new guard is dropped.
+pub fn inject(fault: Fault) -> FaultGuard {
+    let rng = match fault {
+        Fault::Random { seed, .. } => seed,
+        _ => 0,
+    };
+    let state = State {
+        fault,
+        allocations: 0,
+        failures: 0,
+        rng,
+    };
+    FaultGuard {
+        previous: STATE.with(|s| s.replace(Some(state))),
+        _marker: PhantomData,
+    }
+}
+
+/// Returns the number of allocations on the current thread which were failed by the fault
+/// currently injected. Returns 0 if there is no fault.
+pub fn failures() -> usize {
+    STATE.with(|state| state.get().map_or(0, |s| s.failures))
+}
+
+/// Returns the next number from the SplitMix64 generator.
+fn next_random(rng: &mut u64) -> u64 {
+    *rng = rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *rng;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Decides whether an allocation of `size` bytes should fail, according to the fault injected on
+/// the current thread.
+pub(crate) fn should_fail(size: usize) -> bool {
+    // the thread-local may already be destroyed if we are called from another destructor.
+    STATE
+        .try_with(|cell| {
+            let mut state = match cell.get() {
+                Some(state) => state,
+                None => return false,
+            };
+            let fail = match state.fault {
+                Fault::Nth(n) => state.allocations == n,
+                Fault::Above(limit) => size > limit,
+                Fault::Random { probability, .. } => {
+                    // use the top 53 bits to produce a uniform float in [0, 1).
+                    let sample = (next_random(&mut state.rng) >> 11) as f64 / (1_u64 << 53) as f64;
+                    sample < probability
+                }
+            };
+            state.allocations += 1;
+            if fail {
+                state.failures += 1;
+            }
+            cell.set(Some(state));
+            fail
+        })
+        .unwrap_or(false)
+}
+
+#[test]
+fn test_fail_nth() {
+    let _guard = inject(Fault::Nth(2));
+    let a = crate::MBox::try_new(1_u8).unwrap();
+    let mut vec = crate::MVec::try_with_capacity(1).unwrap();
+    vec.push(1_u32);
+    assert!(vec.try_push(2).is_err());
+    assert_eq!(vec.as_slice(), &[1]);
+    assert!(vec.try_push(2).is_ok());
+    assert_eq!(vec.as_slice(), &[1, 2]);
+    assert_eq!(*a, 1);
+    assert_eq!(failures(), 1);
+}
+
+#[test]
+fn test_fail_above() {
+    let _guard = inject(Fault::Above(64));
+    assert!(crate::MBox::<[u64]>::try_new_uninit_slice(8).is_ok());
+    let err = crate::MBox::<[u64]>::try_new_uninit_slice(9).err().unwrap();
+    assert_eq!(err.layout().map(|layout| layout.size()), Some(72));
+    assert!(crate::MString::try_from_str(&"x".repeat(64)).is_err());
+    assert!(crate::MString::try_from_str(&"x".repeat(63)).is_ok());
+
+    let mut vec = crate::MVec::<u8>::new();
+    assert!(vec.try_reserve_exact(64).is_ok());
+    assert!(vec.try_reserve_exact(65).is_err());
+    assert_eq!(vec.capacity(), 64);
+    assert_eq!(failures(), 3);
+}
+
+#[test]
+fn test_fail_random() {
+    fn pattern(seed: u64) -> Vec<bool> {
+        let _guard = inject(Fault::Random {
+            seed,
+            probability: 0.5,
+        });
+        (0..64).map(|i| crate::MBox::try_new(i).is_err()).collect()
+    }
+
+    let first = pattern(42);
+    assert_eq!(first, pattern(42));
+    assert_ne!(first, pattern(43));
+    let failed = first.iter().filter(|&&f| f).count();
+    assert!(failed > 0 && failed < 64);
+
+    let _guard = inject(Fault::Random {
+        seed: 42,
+        probability: 0.0,
+    });
+    assert!((0..64).all(|i| crate::MBox::try_new(i).is_ok()));
+}
+
+#[test]
+fn test_fault_guard() {
+    let outer = inject(Fault::Above(0));
+    assert!(crate::MBox::try_new(1_u8).is_err());
+    {
+        let _inner = inject(Fault::Nth(1));
+        assert!(crate::MBox::try_new(1_u8).is_ok());
+        assert_eq!(failures(), 0);
+    }
+    assert!(crate::MBox::try_new(1_u8).is_err());
+    assert_eq!(failures(), 2);
+    drop(outer);
+    assert!(crate::MBox::try_new(1_u8).is_ok());
+    assert_eq!(failures(), 0);
+
+    let _guard = inject(Fault::Above(0));
+    std::thread::spawn(|| assert!(crate::MBox::try_new(1_u8).is_ok()))
+        .join()
+        .unwrap();
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+#[should_panic(expected = "allocation of 4 bytes failed")]
+fn test_fail_infallible() {
+    // the hook is process-wide, so restore the default one even when the test panics.
+    struct HookGuard;
+    impl Drop for HookGuard {
+        fn drop(&mut self) {
+            let _ = std::alloc::take_alloc_error_hook();
+        }
+    }
+
+    std::alloc::set_alloc_error_hook(|layout| {
+        panic!("allocation of {} bytes failed", layout.size());
+    });
+    let _hook_guard = HookGuard;
+    let _guard = inject(Fault::Nth(0));
+    drop(crate::MBox::new(1_u32));
+}
@@ -1,8 +1,15 @@
 //! Trait to instruct how to properly drop and free pointers.
 
+use libc::c_void;
+
+#[cfg(not(feature = "std"))]
+use alloc::{rc::Rc, sync::Arc};
+use std::mem::align_of;
 use std::ptr::{drop_in_place, NonNull};
+#[cfg(feature = "std")]
+use std::{rc::Rc, sync::Arc};
 
-use crate::internal::gen_free;
+use crate::internal::{gen_free, record_adopt, record_release};
 
 /// Implemented for pointers which can be freed.
 pub trait Free {
@@ -16,6 +23,13 @@ pub trait Free {
     /// flag to track if the pointer has been freed or not (the Rust compiler will automatically do
     /// this with a `Drop` type).
     unsafe fn free(ptr: NonNull<Self>);
+
+    /// Returns the alignment required by the pointee without dereferencing the pointer, so that
+    /// adopted pointers can be validated before they are used. Returns 1 if unknown.
+    #[doc(hidden)]
+    fn align_of_raw(_ptr: NonNull<Self>) -> usize {
+        1
+    }
 }
 
 /// Drops the content of `*ptr`, then frees the `ptr` itself.
@@ -34,6 +48,10 @@ impl<T> Free for T {
     unsafe fn free(ptr_ref: NonNull<Self>) {
         free_ptr_ref(ptr_ref);
     }
+
+    fn align_of_raw(_: NonNull<Self>) -> usize {
+        align_of::<T>()
+    }
 }
 
 impl<T> Free for [T] {
@@ -42,6 +60,10 @@ impl<T> Free for [T] {
         drop_in_place(fat_ptr);
         gen_free(NonNull::new_unchecked(fat_ptr as *mut T));
     }
+
+    fn align_of_raw(_: NonNull<Self>) -> usize {
+        align_of::<T>()
+    }
 }
 
 impl Free for str {
@@ -49,3 +71,276 @@ impl Free for str {
         Free::free(NonNull::new_unchecked(fat_ptr.as_ptr() as *mut [u8]));
     }
 }
+
+/// Implemented for deallocators which release memory obtained from a `malloc`-family allocator.
+///
+/// `MBox`, `MArray` and `MString` take a deallocator type parameter, which defaults to
+/// `LibcFree`. Memory from other C libraries which must be released with their own functions
+/// (e.g. `g_free()` or `sqlite3_free()`) can be owned by defining a zero-sized deallocator:
+///
+/// ```rust
+/// extern crate libc;
+/// extern crate mbox;
+///
+/// use libc::c_void;
+/// use mbox::free::Dealloc;
+/// use mbox::MBox;
+/// use std::ptr::NonNull;
+///
+/// // Pretend this is `g_free()`.
+/// unsafe fn my_free(ptr: *mut c_void) {
+///     libc::free(ptr);
+/// }
+///
+/// struct MyFree;
+///
+/// impl Dealloc for MyFree {
+///     unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+///         my_free(ptr.as_ptr());
+///     }
+/// }
+///
+/// let ptr = unsafe { libc::strdup(b"hello\0".as_ptr() as *const libc::c_char) };
+/// let bytes = unsafe { MBox::from_raw_parts_in(ptr as *mut u8, 5, MyFree) };
+/// assert_eq!(&*bytes, b"hello");
+/// ```
+///
+/// The deallocator is stored inside the box, so it may also carry state, such as the context
+/// pointer required by pool-based allocators (`free(ctx, ptr)`). Deallocators shared by many boxes
+/// can be stored by reference, or by `Rc`/`Arc`:
+///
+/// ```rust
+/// extern crate libc;
+/// extern crate mbox;
+///
+/// use libc::c_void;
+/// use mbox::free::Dealloc;
+/// use mbox::MBox;
+/// use std::cell::Cell;
+/// use std::ptr::NonNull;
+///
+/// // Pretend this is a handle to a memory pool.
+/// #[derive(Default)]
+/// struct Pool {
+///     live: Cell<usize>,
+/// }
+///
+/// impl Pool {
+///     fn alloc(&self, size: usize) -> *mut c_void {
+///         self.live.set(self.live.get() + 1);
+///         unsafe { libc::malloc(size) }
+///     }
+/// }
+///
+/// impl Dealloc for Pool {
+///     unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+///         self.live.set(self.live.get() - 1);
+///         libc::free(ptr.as_ptr());
+///     }
+/// }
+///
+/// let pool = Pool::default();
+/// let a = unsafe { MBox::from_raw_parts_in(pool.alloc(4) as *mut u8, 0, &pool) };
+/// let b = unsafe { MBox::from_raw_parts_in(pool.alloc(4) as *mut u8, 0, &pool) };
+/// assert_eq!(pool.live.get(), 2);
+/// drop((a, b));
+/// assert_eq!(pool.live.get(), 0);
+/// ```
+pub trait Dealloc {
+    /// Releases the memory pointed by this pointer, without dropping the content.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be allocated by the allocator corresponding to this deallocator, and must not
+    /// be used after this call.
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>);
+
+    /// Drops the content pointed by this pointer, then releases the memory.
+    ///
+    /// # Safety
+    ///
+    /// Same as `dealloc()`. The content of the pointer must be initialized.
+    unsafe fn drop_and_dealloc<T: ?Sized + Free>(&self, ptr: NonNull<T>) {
+        drop_in_place(ptr.as_ptr());
+        self.dealloc(ptr.cast());
+    }
+
+    /// Called when a pointer is handed over to a box using this deallocator. `LibcFree` uses this
+    /// to record the pointer in the allocation registry and the statistics. Does nothing by
+    /// default.
+    #[doc(hidden)]
+    fn adopt(&self, _ptr: NonNull<c_void>) {}
+
+    /// Called when a box using this deallocator gives up a pointer without releasing the memory,
+    /// i.e. the reverse of `adopt()`. Does nothing by default.
+    #[doc(hidden)]
+    fn release(&self, _ptr: NonNull<c_void>) {}
+}
+
+/// The default deallocator, which releases memory using libc's `free()`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct LibcFree;
+
+impl Dealloc for LibcFree {
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+        gen_free(ptr);
+    }
+
+    unsafe fn drop_and_dealloc<T: ?Sized + Free>(&self, ptr: NonNull<T>) {
+        T::free(ptr);
+    }
+
+    fn adopt(&self, ptr: NonNull<c_void>) {
+        record_adopt(ptr.as_ptr());
+    }
+
+    fn release(&self, ptr: NonNull<c_void>) {
+        record_release(ptr.as_ptr());
+    }
+}
+
+/// A deallocator which releases memory using a function pointer chosen at runtime, e.g. the
+/// `free` callback from a plugin's vtable.
+///
+/// ```rust
+/// extern crate libc;
+/// extern crate mbox;
+///
+/// use mbox::free::FnFree;
+/// use mbox::MString;
+///
+/// let ptr = unsafe { libc::strdup(b"hello\0".as_ptr() as *const libc::c_char) };
+/// let string = unsafe { MString::from_raw_in(ptr, FnFree(libc::free)) }.unwrap();
+/// assert_eq!(&*string, "hello");
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct FnFree(pub unsafe extern "C" fn(*mut c_void));
+
+impl Dealloc for FnFree {
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+        (self.0)(ptr.as_ptr());
+    }
+}
+
+impl<D: Dealloc> Dealloc for &D {
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+        (**self).dealloc(ptr);
+    }
+
+    unsafe fn drop_and_dealloc<T: ?Sized + Free>(&self, ptr: NonNull<T>) {
+        (**self).drop_and_dealloc(ptr);
+    }
+
+    fn adopt(&self, ptr: NonNull<c_void>) {
+        (**self).adopt(ptr);
+    }
+
+    fn release(&self, ptr: NonNull<c_void>) {
+        (**self).release(ptr);
+    }
+}
+
+impl<D: Dealloc> Dealloc for Rc<D> {
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+        (**self).dealloc(ptr);
+    }
+
+    unsafe fn drop_and_dealloc<T: ?Sized + Free>(&self, ptr: NonNull<T>) {
+        (**self).drop_and_dealloc(ptr);
+    }
+
+    fn adopt(&self, ptr: NonNull<c_void>) {
+        (**self).adopt(ptr);
+    }
+
+    fn release(&self, ptr: NonNull<c_void>) {
+        (**self).release(ptr);
+    }
+}
+
+impl<D: Dealloc> Dealloc for Arc<D> {
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+        (**self).dealloc(ptr);
+    }
+
+    unsafe fn drop_and_dealloc<T: ?Sized + Free>(&self, ptr: NonNull<T>) {
+        (**self).drop_and_dealloc(ptr);
+    }
+
+    fn adopt(&self, ptr: NonNull<c_void>) {
+        (**self).adopt(ptr);
+    }
+
+    fn release(&self, ptr: NonNull<c_void>) {
+        (**self).release(ptr);
+    }
+}
+
+/// Implemented for destructors which release a C resource referred by a handle of type `*mut T`,
+/// e.g. `fclose()` or `sqlite3_finalize()`. Used by `MHandle<T, F>`.
+///
+/// This is implemented for `unsafe extern "C" fn(*mut T) -> R`, where the return value is ignored.
+pub trait Destructor<T> {
+    /// Destroys the resource referred by the handle.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be a valid handle which is expected to be destroyed by this destructor, and
+    /// must not be used after this call.
+    unsafe fn destroy(&self, ptr: NonNull<T>);
+}
+
+impl<T, R> Destructor<T> for unsafe extern "C" fn(*mut T) -> R {
+    unsafe fn destroy(&self, ptr: NonNull<T>) {
+        self(ptr.as_ptr());
+    }
+}
+
+/// Marker trait for destructors whose handles can be transferred to another thread, making
+/// `MHandle<T, F>` implement `Send`.
+///
+/// # Safety
+///
+/// The implementor must ensure the underlying C resource can be used and destroyed from a thread
+/// different from the one creating it.
+pub unsafe trait SendDestructor {}
+
+/// Marker trait for destructors whose handles can be shared between threads, making
+/// `MHandle<T, F>` implement `Sync`.
+///
+/// # Safety
+///
+/// The implementor must ensure the underlying C resource can be accessed from multiple threads
+/// simultaneously through a shared `MHandle`.
+pub unsafe trait SyncDestructor {}
+
+/// A destructor wrapper asserting that the handles are thread-safe, for destructors which cannot
+/// implement `SendDestructor` and `SyncDestructor` directly (e.g. function pointers).
+#[derive(Copy, Clone, Debug)]
+pub struct AssertThreadSafe<F>(F);
+
+impl<F> AssertThreadSafe<F> {
+    /// Wraps a destructor.
+    ///
+    /// # Safety
+    ///
+    /// The resources destroyed by `destructor` must satisfy the requirements of both
+    /// `SendDestructor` and `SyncDestructor`.
+    pub unsafe fn new(destructor: F) -> Self {
+        AssertThreadSafe(destructor)
+    }
+
+    /// Unwraps the destructor.
+    pub fn into_inner(self) -> F {
+        self.0
+    }
+}
+
+impl<T, F: Destructor<T>> Destructor<T> for AssertThreadSafe<F> {
+    unsafe fn destroy(&self, ptr: NonNull<T>) {
+        self.0.destroy(ptr);
+    }
+}
+
+unsafe impl<F> SendDestructor for AssertThreadSafe<F> {}
+unsafe impl<F> SyncDestructor for AssertThreadSafe<F> {}
@@ -0,0 +1,210 @@
+//! `malloc`-based allocators for the standard containers.
+
+use libc::c_void;
+
+use std::alloc::{GlobalAlloc, Layout};
+
+#[cfg(feature = "nightly")]
+use std::alloc::{AllocError as StdAllocError, Allocator};
+#[cfg(feature = "nightly")]
+use std::ptr::{write_bytes, NonNull};
+
+use crate::internal::{calloc_raw, malloc_raw, realloc_raw};
+
+/// A global allocator which obtains memory from the system's `malloc()` and releases it with
+/// `free()`.
+///
+/// Rust's default global allocator is not guaranteed to be compatible with `free()`, so moving a
+/// `Box<T>` or `Vec<T>` into an `MBox` normally requires a copy. Registering `Malloc` as the global
+/// allocator makes every Rust heap allocation `free()`-compatible:
+///
+/// ```rust
+/// #[global_allocator]
+/// static GLOBAL: mbox::Malloc = mbox::Malloc;
+/// # fn main() {}
+/// ```
+///
+/// With the `global_malloc` feature enabled, the zero-copy conversions `MBox::from_box`,
+/// `MBox::<[T]>::from_vec`, `MBox::<str>::from_string` and their reverse become available.
+///
+/// On Windows, allocations with alignment larger than what `malloc()` guarantees will fail.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Malloc;
+
+unsafe impl GlobalAlloc for Malloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        malloc_raw(layout.size(), layout.align()) as *mut u8
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        calloc_raw(layout.size(), layout.align()) as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
+        libc::free(ptr as *mut c_void);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        realloc_raw(ptr as *mut c_void, layout.size(), new_size, layout.align()) as *mut u8
+    }
+}
+
+/// An allocator for the standard containers (e.g. `Vec<T, MallocAlloc>`) which obtains memory from
+/// the system's `malloc()` and releases it with `free()`.
+///
+/// Containers built with this allocator can be converted to and from `MBox<T>`, `MBox<[T]>` and
+/// `MBox<str>` without copying, regardless of the global allocator.
+#[cfg(feature = "nightly")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MallocAlloc;
+
+// `Allocator` is only available on nightly, so the MSRV does not apply to this impl.
+#[cfg(feature = "nightly")]
+#[allow(clippy::incompatible_msrv)]
+unsafe impl Allocator for MallocAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, StdAllocError> {
+        // allocate at least 1 byte so that a zero-sized request still produces a unique pointer.
+        // SAFETY: allocating should be safe, duh.
+        let res = unsafe { malloc_raw(layout.size().max(1), layout.align()) };
+        NonNull::new(res as *mut u8)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+            .ok_or(StdAllocError)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, StdAllocError> {
+        // SAFETY: allocating should be safe, duh.
+        let res = unsafe { calloc_raw(layout.size().max(1), layout.align()) };
+        NonNull::new(res as *mut u8)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+            .ok_or(StdAllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
+        libc::free(ptr.as_ptr() as *mut c_void);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, StdAllocError> {
+        self.shrink(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, StdAllocError> {
+        let res = self.grow(ptr, old_layout, new_layout)?;
+        let old_size = old_layout.size();
+        write_bytes(
+            res.as_ptr().cast::<u8>().add(old_size),
+            0,
+            new_layout.size() - old_size,
+        );
+        Ok(res)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, StdAllocError> {
+        let res = realloc_raw(
+            ptr.as_ptr() as *mut c_void,
+            old_layout.size(),
+            new_layout.size().max(1),
+            new_layout.align(),
+        );
+        NonNull::new(res as *mut u8)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+            .ok_or(StdAllocError)
+    }
+}
+
+#[test]
+fn test_global_alloc() {
+    unsafe {
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = Malloc.alloc_zeroed(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 8, 0);
+        assert_eq!(*ptr.add(23), 0);
+        *ptr.add(23) = 7;
+
+        let ptr = Malloc.realloc(ptr, layout, 4096);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 8, 0);
+        assert_eq!(*ptr.add(23), 7);
+        Malloc.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap());
+    }
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_global_alloc_over_aligned() {
+    unsafe {
+        let layout = Layout::from_size_align(100, 256).unwrap();
+        let ptr = Malloc.alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 256, 0);
+        *ptr.add(99) = 42;
+
+        let ptr = Malloc.realloc(ptr, layout, 100_000);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 256, 0);
+        assert_eq!(*ptr.add(99), 42);
+
+        let new_layout = Layout::from_size_align(100_000, 256).unwrap();
+        let ptr = Malloc.realloc(ptr, new_layout, 50);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 256, 0);
+        Malloc.dealloc(ptr, Layout::from_size_align(50, 256).unwrap());
+
+        let ptr = Malloc.alloc_zeroed(new_layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 256, 0);
+        assert!((0..100_000).all(|i| *ptr.add(i) == 0));
+        Malloc.dealloc(ptr, new_layout);
+    }
+}
+
+#[cfg(all(feature = "nightly", not(windows)))]
+#[test]
+fn test_allocator() {
+    #[cfg(not(feature = "std"))]
+    use alloc::vec::Vec;
+
+    let mut vec = Vec::new_in(MallocAlloc);
+    vec.extend(0..1000_u64);
+    assert_eq!(vec.iter().sum::<u64>(), 499_500);
+    vec.truncate(3);
+    vec.shrink_to_fit();
+    assert_eq!(vec.as_slice(), &[0, 1, 2]);
+
+    #[repr(align(256))]
+    struct OverAligned(u8);
+
+    let mut vec = Vec::new_in(MallocAlloc);
+    for i in 0..100 {
+        vec.push(OverAligned(i));
+        assert_eq!(vec.as_ptr() as usize % 256, 0);
+    }
+    assert!(vec.iter().enumerate().all(|(i, x)| x.0 as usize == i));
+
+    unsafe {
+        let layout = Layout::from_size_align(0, 1).unwrap();
+        let ptr = MallocAlloc.allocate_zeroed(layout).unwrap();
+        assert_eq!(ptr.len(), 0);
+        let new_layout = Layout::from_size_align(64, 1).unwrap();
+        let ptr = MallocAlloc
+            .grow_zeroed(ptr.cast(), layout, new_layout)
+            .unwrap();
+        assert!((0..64).all(|i| *ptr.as_ptr().cast::<u8>().add(i) == 0));
+        MallocAlloc.deallocate(ptr.cast(), new_layout);
+    }
+}
@@ -0,0 +1,150 @@
+//! Introspection of the glibc `malloc` heap.
+//!
+//! This module wraps the glibc-specific functions reporting and tuning the state of the heap which
+//! the memory owned by `mbox` lives in, e.g. to diagnose fragmentation:
+//!
+//! ```rust
+//! extern crate mbox;
+//!
+//! use mbox::heap;
+//!
+//! let info = heap::info();
+//! println!("{} bytes in use, {} bytes free", info.allocated_bytes, info.free_bytes);
+//!
+//! let xml = heap::info_xml().unwrap();
+//! assert!(xml.starts_with("<malloc version="));
+//! ```
+//!
+//! The module requires the `heap_info` feature and is only available on Linux with glibc.
+//! `info()` requires glibc 2.33 or above.
+
+// recent versions of `libc` re-export `c_char` and `c_int` from `core::ffi`, which this lint
+// mistakes for the items stabilized in Rust 1.64.
+#![allow(clippy::incompatible_msrv)]
+
+use libc::{c_char, c_int, c_void, size_t, FILE};
+
+use std::ptr::null_mut;
+
+use crate::sentinel::MString;
+
+/// A summary of all arenas of the heap returned by `mallinfo2()`. All sizes are in bytes.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct HeapInfo {
+    /// Non-mmapped space allocated from the system (`arena`).
+    pub arena_bytes: usize,
+    /// Number of free chunks (`ordblks`).
+    pub free_chunks: usize,
+    /// Number of free fastbin blocks (`smblks`).
+    pub free_fastbin_blocks: usize,
+    /// Number of regions allocated by `mmap()` (`hblks`).
+    pub mmapped_regions: usize,
+    /// Space allocated in the regions allocated by `mmap()` (`hblkhd`).
+    pub mmapped_bytes: usize,
+    /// Space in the free fastbin blocks (`fsmblks`).
+    pub free_fastbin_bytes: usize,
+    /// Total allocated space (`uordblks`).
+    pub allocated_bytes: usize,
+    /// Total free space (`fordblks`).
+    pub free_bytes: usize,
+    /// Space at the top of the heap which can be released by `trim()` (`keepcost`).
+    pub releasable_bytes: usize,
+}
+
+/// Returns a summary of the heap using `mallinfo2()`.
+pub fn info() -> HeapInfo {
+    let info = unsafe { libc::mallinfo2() };
+    HeapInfo {
+        arena_bytes: info.arena,
+        free_chunks: info.ordblks,
+        free_fastbin_blocks: info.smblks,
+        mmapped_regions: info.hblks,
+        mmapped_bytes: info.hblkhd,
+        free_fastbin_bytes: info.fsmblks,
+        allocated_bytes: info.uordblks,
+        free_bytes: info.fordblks,
+        releasable_bytes: info.keepcost,
+    }
+}
+
+/// Releases the free memory of the heap back to the system using `malloc_trim()`, keeping `pad`
+/// bytes untrimmed at the top of the main arena.
+///
+/// Returns whether any memory was actually released.
+pub fn trim(pad: usize) -> bool {
+    unsafe { libc::malloc_trim(pad) != 0 }
+}
+
+/// Prints the statistics of every arena to the standard error using `malloc_stats()`.
+pub fn print_stats() {
+    unsafe { libc::malloc_stats() }
+}
+
+/// Returns an XML description of the heap produced by `malloc_info()`.
+///
+/// Returns `None` if the output could not be captured.
+pub fn info_xml() -> Option<MString> {
+    capture(|stream| unsafe { libc::malloc_info(0, stream) })
+}
+
+/// Captures everything written to a `FILE` by `write` into an `MString` using `open_memstream()`.
+/// Returns `None` if the stream cannot be opened or `write` returns a nonzero status.
+fn capture<F: FnOnce(*mut FILE) -> c_int>(write: F) -> Option<MString> {
+    let mut buffer: *mut c_char = null_mut();
+    let mut size: size_t = 0;
+    unsafe {
+        let stream = libc::open_memstream(&mut buffer, &mut size);
+        if stream.is_null() {
+            return None;
+        }
+        let status = write(stream);
+        // the buffer is only guaranteed to be allocated and null-terminated after closing.
+        let closed = libc::fclose(stream);
+        if buffer.is_null() {
+            return None;
+        }
+        if status != 0 || closed != 0 {
+            libc::free(buffer as *mut c_void);
+            return None;
+        }
+        Some(MString::from_raw_lossy(buffer))
+    }
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_info() {
+    let boxed = crate::MBox::<[u8]>::new_zeroed_slice(1 << 16);
+    let info = info();
+    assert!(info.allocated_bytes + info.mmapped_bytes >= 1 << 16);
+    assert!(info.arena_bytes >= info.allocated_bytes);
+    drop(boxed);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_trim() {
+    drop(crate::MBox::<[u8]>::new_zeroed_slice(1 << 16));
+    trim(0);
+    let info = info();
+    assert!(info.releasable_bytes <= info.free_bytes);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_info_xml() {
+    let xml = info_xml().unwrap();
+    assert!(xml.starts_with("<malloc version="));
+    assert!(xml.trim_end().ends_with("</malloc>"));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_capture() {
+    let output = capture(|stream| unsafe {
+        libc::fputs(b"hello\0".as_ptr() as *const c_char, stream);
+        0
+    });
+    assert_eq!(output.as_ref().map(|s| &**s), Some("hello"));
+    assert!(capture(|_| -1).is_none());
+}
@@ -1,15 +1,13 @@
-#[cfg(not(feature = "std"))]
-extern crate alloc;
-
 use libc::c_void;
 
 use std::alloc::Layout;
+use std::fmt::{Display, Formatter, Result as FormatResult};
 use std::marker::PhantomData;
 use std::mem::{align_of, size_of};
-use std::ptr::{copy_nonoverlapping, NonNull};
+use std::ptr::{copy_nonoverlapping, write_bytes, NonNull};
 
 #[cfg(not(feature = "std"))]
-use self::alloc::alloc::handle_alloc_error;
+use alloc::alloc::handle_alloc_error;
 #[cfg(feature = "std")]
 use std::alloc::handle_alloc_error;
 
@@ -76,22 +74,240 @@ unsafe fn malloc_aligned<T>(size: usize) -> *mut c_void {
         "Windows malloc() only support alignment of 1"
     );
 
-    libc::malloc(size)
+    malloc_raw(size, align_of::<T>())
 }
 
-#[cfg(all(not(windows), target_os = "android"))]
+#[cfg(not(windows))]
 unsafe fn malloc_aligned<T>(size: usize) -> *mut c_void {
-    libc::memalign(align_of::<T>(), size)
+    malloc_raw(size, align_of::<T>())
+}
+
+/// Allocates `size` bytes aligned to `align`, which must be a power of 2. Returns null on failure.
+///
+/// On Windows, alignment larger than what `malloc()` guarantees is not supported and always fails.
+#[cfg(windows)]
+pub(crate) unsafe fn malloc_raw(size: usize, align: usize) -> *mut c_void {
+    if align <= MIN_MALLOC_ALIGN {
+        libc::malloc(size)
+    } else {
+        std::ptr::null_mut()
+    }
+}
+
+#[cfg(all(not(windows), target_os = "android"))]
+pub(crate) unsafe fn malloc_raw(size: usize, align: usize) -> *mut c_void {
+    libc::memalign(align, size)
 }
 
 #[cfg(all(not(windows), not(target_os = "android")))]
-unsafe fn malloc_aligned<T>(size: usize) -> *mut c_void {
+pub(crate) unsafe fn malloc_raw(size: usize, align: usize) -> *mut c_void {
     let mut result = std::ptr::null_mut();
-    let align = align_of::<T>().max(size_of::<*mut ()>());
+    let align = align.max(size_of::<*mut ()>());
     libc::posix_memalign(&mut result, align, size);
     result
 }
 
+/// The minimum alignment guaranteed by `malloc()` and `calloc()` for every allocation.
+///
+/// This is the `MALLOC_ALIGNMENT` of glibc, which is also satisfied by other common libc
+/// implementations.
+pub(crate) const MIN_MALLOC_ALIGN: usize = 2 * size_of::<usize>();
+
+/// Allocates `size` bytes of zeroed memory aligned to `align`, which must be a power of 2. Returns
+/// null on failure.
+///
+/// If the alignment is small enough, this calls `calloc()` directly to take advantage of its
+/// optimizations (e.g. pages obtained fresh from the kernel are already zeroed). Otherwise, we fall
+/// back to an aligned `malloc()` followed by a `memset()`.
+pub(crate) unsafe fn calloc_raw(size: usize, align: usize) -> *mut c_void {
+    if align <= MIN_MALLOC_ALIGN {
+        libc::calloc(size, 1)
+    } else {
+        let res = malloc_raw(size, align);
+        if !res.is_null() {
+            write_bytes(res as *mut u8, 0, size);
+        }
+        res
+    }
+}
+
+/// Resizes the allocation at `ptr` from `old_size` to `new_size` bytes aligned to `align`, which
+/// must be a power of 2. Returns null on failure, in which case `ptr` is left untouched.
+///
+/// This is used by the `GlobalAlloc` and `Allocator` implementations, which require the original
+/// pointer to stay valid on failure. Use `try_realloc_raw()` for the containers of `mbox`.
+pub(crate) unsafe fn realloc_raw(
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    align: usize,
+) -> *mut c_void {
+    if align <= MIN_MALLOC_ALIGN {
+        return libc::realloc(ptr, new_size);
+    }
+
+    // We cannot try `realloc()` first and fix up the alignment afterwards,
+    // since `ptr` must stay valid when we return null.
+    let res = malloc_raw(new_size, align);
+    if !res.is_null() {
+        copy_nonoverlapping(ptr as *const u8, res as *mut u8, old_size.min(new_size));
+        libc::free(ptr);
+    }
+    res
+}
+
+/// Resizes the allocation at `ptr` from `old_size` to `new_size` bytes aligned to `align`, which
+/// must be a power of 2, calling `realloc()` first even for alignments larger than
+/// `MIN_MALLOC_ALIGN` so that the allocation can often be resized in place.
+///
+/// On failure, returns the allocation now holding the content, which is aligned to `align` and
+/// spans at least `old_size` bytes. This is `ptr` itself, unless `realloc()` has moved the content
+/// to a misaligned address and no aligned block of `new_size` bytes could be allocated to move it
+/// again.
+pub(crate) unsafe fn try_realloc_raw(
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    align: usize,
+) -> Result<*mut c_void, *mut c_void> {
+    let res = libc::realloc(ptr, new_size);
+    if res.is_null() {
+        return Err(ptr);
+    }
+    if res as usize % align == 0 {
+        return Ok(res);
+    }
+
+    // Most system don't provide an `aligned_realloc`.
+    // If `libc::realloc()` does not give us an aligned pointer,
+    // we have to perform an additional aligned allocation and memcpy over.
+    let copy_len = old_size.min(new_size);
+    let actual_res = malloc_raw(new_size, align);
+    if !actual_res.is_null() {
+        copy_nonoverlapping(res as *const u8, actual_res as *mut u8, copy_len);
+        libc::free(res);
+        return Ok(actual_res);
+    }
+
+    // `ptr` is gone, so move the content back into an aligned block of the original size, which
+    // is the only way to report the failure without leaving the caller a misaligned pointer.
+    let fallback = malloc_raw(old_size, align);
+    if fallback.is_null() {
+        handle_alloc_error(Layout::from_size_align_unchecked(old_size, align));
+    }
+    copy_nonoverlapping(res as *const u8, fallback as *mut u8, copy_len);
+    libc::free(res);
+    Err(fallback)
+}
+
+/// The error type returned by the fallible allocation methods (e.g. `MBox::try_new`).
+///
+/// This error is produced when `malloc()` returns NULL, when the requested size overflows
+/// `usize`, or when the requested alignment is not a power of 2.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AllocError {
+    kind: AllocErrorKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AllocErrorKind {
+    CapacityOverflow,
+    InvalidAlignment,
+    Failed(Layout),
+}
+
+impl AllocError {
+    /// Creates an error representing an arithmetic overflow when computing the allocation size.
+    pub(crate) fn capacity_overflow() -> Self {
+        Self {
+            kind: AllocErrorKind::CapacityOverflow,
+        }
+    }
+
+    /// Creates an error representing a requested alignment which is not a power of 2.
+    pub(crate) fn invalid_alignment() -> Self {
+        Self {
+            kind: AllocErrorKind::InvalidAlignment,
+        }
+    }
+
+    /// Creates an error representing a failed allocation of `size` bytes aligned to `T`.
+    pub(crate) fn failed<T>(size: usize) -> Self {
+        Self::failed_aligned(size, align_of::<T>())
+    }
+
+    /// Creates an error representing a failed allocation of `size` bytes aligned to `align`.
+    pub(crate) fn failed_aligned(size: usize, align: usize) -> Self {
+        match Layout::from_size_align(size, align) {
+            Ok(layout) => Self {
+                kind: AllocErrorKind::Failed(layout),
+            },
+            Err(_) => Self::capacity_overflow(),
+        }
+    }
+
+    /// Returns the layout of the allocation which failed.
+    ///
+    /// Returns `None` if the error was caused by the requested size overflowing `usize`, or by an
+    /// invalid alignment.
+    pub fn layout(&self) -> Option<Layout> {
+        match self.kind {
+            AllocErrorKind::Failed(layout) => Some(layout),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the error was caused by the requested size overflowing `usize`.
+    pub fn is_capacity_overflow(&self) -> bool {
+        self.kind == AllocErrorKind::CapacityOverflow
+    }
+
+    /// Returns whether the error was caused by the requested alignment not being a power of 2.
+    pub fn is_invalid_alignment(&self) -> bool {
+        self.kind == AllocErrorKind::InvalidAlignment
+    }
+
+    /// Reports the error using the same mechanism as the infallible allocation functions, i.e.
+    /// panics on overflow or invalid alignment, and calls `handle_alloc_error` on out-of-memory.
+    pub(crate) fn handle(self) -> ! {
+        match self.kind {
+            AllocErrorKind::Failed(layout) => handle_alloc_error(layout),
+            AllocErrorKind::CapacityOverflow => panic!("memory overflow"),
+            AllocErrorKind::InvalidAlignment => panic!("alignment must be a power of 2"),
+        }
+    }
+}
+
+impl Display for AllocError {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter.write_str(match self.kind {
+            AllocErrorKind::CapacityOverflow => {
+                "memory allocation failed because the requested size overflowed"
+            }
+            AllocErrorKind::InvalidAlignment => {
+                "memory allocation failed because the requested alignment is not a power of 2"
+            }
+            AllocErrorKind::Failed(_) => "memory allocation failed",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AllocError {}
+
+/// Converts the result of an allocation into a `NonNull`, recording it in the debug registry
+/// and the statistics.
+fn finish_alloc<T>(
+    res: *mut c_void,
+    requested_size: usize,
+    align: usize,
+) -> Result<NonNull<T>, AllocError> {
+    let res = NonNull::new(res as *mut T)
+        .ok_or_else(|| AllocError::failed_aligned(requested_size, align))?;
+    record_alloc(res.as_ptr() as *const c_void, requested_size);
+    Ok(res)
+}
+
 /// Generic malloc function.
 ///
 /// This function allocates memory capable of storing the array `[T; count]`.
@@ -102,7 +318,18 @@ unsafe fn malloc_aligned<T>(size: usize) -> *mut c_void {
 /// byte* to respect the `NonNull` constraint (we cannot use `NonNull::danging()`
 /// because we allow the result to be passed directly to C's `free()`).
 pub fn gen_malloc<T>(count: usize) -> NonNull<T> {
-    let requested_size = count.checked_mul(size_of::<T>()).expect("memory overflow");
+    try_gen_malloc(count).unwrap_or_else(|e| e.handle())
+}
+
+/// Generic fallible malloc function.
+///
+/// Same as `gen_malloc()`, but returns an `AllocError` instead of aborting when the memory cannot
+/// be allocated.
+pub fn try_gen_malloc<T>(count: usize) -> Result<NonNull<T>, AllocError> {
+    let requested_size = count
+        .checked_mul(size_of::<T>())
+        .ok_or_else(AllocError::capacity_overflow)?;
+    inject_fault(requested_size, align_of::<T>())?;
 
     let mut res;
     // SAFETY: allocating should be safe, duh.
@@ -112,7 +339,125 @@ pub fn gen_malloc<T>(count: usize) -> NonNull<T> {
             res = malloc_aligned::<T>(align_of::<T>());
         }
     }
-    NonNull::new(res as *mut T).unwrap_or_else(|| handle_alloc_error(Layout::new::<T>()))
+    finish_alloc(res, requested_size, align_of::<T>())
+}
+
+/// Generic fallible calloc function.
+///
+/// This function allocates memory capable of storing the array `[T; count]`.
+/// The memory content will be filled with zeros.
+///
+/// Like `gen_malloc()`, this will *allocate at least 1 byte* even if `T` is zero-sized or
+/// `count == 0`. Returns an `AllocError` when the memory cannot be allocated.
+pub fn try_gen_calloc<T>(count: usize) -> Result<NonNull<T>, AllocError> {
+    let requested_size = count
+        .checked_mul(size_of::<T>())
+        .ok_or_else(AllocError::capacity_overflow)?;
+    inject_fault(requested_size, align_of::<T>())?;
+
+    let mut res;
+    // SAFETY: allocating should be safe, duh.
+    unsafe {
+        res = calloc_raw(requested_size, align_of::<T>());
+        if res.is_null() && requested_size == 0 {
+            res = calloc_raw(align_of::<T>(), align_of::<T>());
+        }
+    }
+    finish_alloc(res, requested_size, align_of::<T>())
+}
+
+/// Allocates memory for `[T; count]` aligned to at least `align` bytes using `alloc_fn`, which is
+/// either `malloc_raw` or `calloc_raw`.
+fn try_gen_alloc_aligned<T>(
+    count: usize,
+    align: usize,
+    alloc_fn: unsafe fn(usize, usize) -> *mut c_void,
+) -> Result<NonNull<T>, AllocError> {
+    if !align.is_power_of_two() {
+        return Err(AllocError::invalid_alignment());
+    }
+    let align = align.max(align_of::<T>());
+    let requested_size = count
+        .checked_mul(size_of::<T>())
+        .ok_or_else(AllocError::capacity_overflow)?;
+    inject_fault(requested_size, align)?;
+
+    let mut res;
+    // SAFETY: allocating should be safe, duh.
+    unsafe {
+        res = alloc_fn(requested_size, align);
+        if res.is_null() && requested_size == 0 {
+            res = alloc_fn(align, align);
+        }
+    }
+    finish_alloc(res, requested_size, align)
+}
+
+/// Generic fallible malloc function with a runtime alignment.
+///
+/// Same as `try_gen_malloc()`, but the result is aligned to at least `align` bytes. Returns an
+/// `AllocError` if `align` is not a power of 2. The memory can still be released with `free()`.
+///
+/// On Windows, alignment larger than what `malloc()` guarantees is not supported and always fails.
+pub fn try_gen_malloc_aligned<T>(count: usize, align: usize) -> Result<NonNull<T>, AllocError> {
+    try_gen_alloc_aligned(count, align, malloc_raw)
+}
+
+/// Generic fallible calloc function with a runtime alignment.
+///
+/// Same as `try_gen_calloc()`, but the result is aligned to at least `align` bytes. Returns an
+/// `AllocError` if `align` is not a power of 2. The memory can still be released with `free()`.
+pub fn try_gen_calloc_aligned<T>(count: usize, align: usize) -> Result<NonNull<T>, AllocError> {
+    try_gen_alloc_aligned(count, align, calloc_raw)
+}
+
+/// The size of a transparent huge page on common Linux platforms.
+#[cfg(target_os = "linux")]
+pub(crate) const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// Advises the kernel to back the whole huge pages within `size` bytes starting at `ptr` with
+/// transparent huge pages.
+///
+/// # Safety
+///
+/// `ptr` must be aligned to `HUGE_PAGE_SIZE` and point to an allocation of at least `size` bytes.
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn advise_huge_pages(ptr: *mut c_void, size: usize) {
+    let len = size - size % HUGE_PAGE_SIZE;
+    if len != 0 {
+        // this is only a hint, so the result is deliberately ignored.
+        libc::madvise(ptr, len, libc::MADV_HUGEPAGE);
+    }
+}
+
+/// Returns the number of bytes which can actually be used in the allocation at `ptr`. This may be
+/// larger than the requested size.
+///
+/// # Safety
+///
+/// The `ptr` must be obtained from `malloc()` or similar C functions.
+#[cfg(target_os = "linux")]
+pub unsafe fn gen_usable_size<T>(ptr: NonNull<T>) -> usize {
+    libc::malloc_usable_size(ptr.as_ptr() as *mut c_void)
+}
+
+/// Returns the number of `T`s which fit in the allocation at `ptr`, known to hold at least `count`
+/// items. Returns `count` when the usable size cannot be queried.
+///
+/// # Safety
+///
+/// The `ptr` must be obtained from `malloc()` or similar C functions.
+#[cfg(all(target_os = "linux", not(miri)))]
+pub(crate) unsafe fn usable_count<T>(ptr: NonNull<T>, count: usize) -> usize {
+    match size_of::<T>() {
+        0 => count,
+        size => count.max(gen_usable_size(ptr) / size),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", not(miri))))]
+pub(crate) unsafe fn usable_count<T>(_: NonNull<T>, count: usize) -> usize {
+    count
 }
 
 /// Generic free function.
@@ -122,6 +467,7 @@ pub fn gen_malloc<T>(count: usize) -> NonNull<T> {
 /// The `ptr` must be obtained from `malloc()` or similar C functions.
 /// The memory content will not be dropped.
 pub unsafe fn gen_free<T>(ptr: NonNull<T>) {
+    record_free(ptr.as_ptr() as *const c_void);
     libc::free(ptr.as_ptr() as *mut c_void);
 }
 
@@ -134,38 +480,180 @@ pub unsafe fn gen_free<T>(ptr: NonNull<T>) {
 /// # Safety
 ///
 /// The `ptr` must be obtained from `malloc()` or similar C functions.
-pub unsafe fn gen_realloc<T>(ptr: NonNull<T>, old_count: usize, new_count: usize) -> NonNull<T> {
+pub unsafe fn gen_realloc<T>(
+    mut ptr: NonNull<T>,
+    old_count: usize,
+    new_count: usize,
+) -> NonNull<T> {
+    match try_gen_realloc(&mut ptr, old_count, new_count) {
+        Ok(()) => ptr,
+        Err(e) => e.handle(),
+    }
+}
+
+/// Generic fallible realloc function.
+///
+/// Same as `gen_realloc()`, but updates `ptr` in place, and returns an `AllocError` instead of
+/// aborting when the memory cannot be allocated. On error, `ptr` still points to an allocation of
+/// `old_count` items with the original content. This is usually the original allocation, but may
+/// be a new one when `T` is aligned beyond what `malloc()` guarantees.
+///
+/// # Safety
+///
+/// The `ptr` must be obtained from `malloc()` or similar C functions, with room for at least
+/// `old_count` items.
+pub unsafe fn try_gen_realloc<T>(
+    ptr: &mut NonNull<T>,
+    old_count: usize,
+    new_count: usize,
+) -> Result<(), AllocError> {
     if size_of::<T>() == 0 {
-        return ptr;
+        return Ok(());
     }
 
-    (|| {
-        // ensure `requested_size > 0` to avoid `realloc()` returning a successful NULL.
-        let requested_size = new_count.checked_mul(size_of::<T>())?.max(align_of::<T>());
-        let mut res = libc::realloc(ptr.as_ptr() as *mut c_void, requested_size);
-        if res.is_null() {
-            return None;
+    // ensure `requested_size > 0` to avoid `realloc()` returning a successful NULL.
+    let requested_size = new_count
+        .checked_mul(size_of::<T>())
+        .ok_or_else(AllocError::capacity_overflow)?
+        .max(align_of::<T>());
+    inject_fault(requested_size, align_of::<T>())?;
+    let old_ptr = ptr.as_ptr() as *mut c_void;
+    let old_tracked_size = tracked_size(old_ptr);
+    // no need to do checked_mul() here since the old allocation must have fit in memory.
+    let old_size = old_count * size_of::<T>();
+    match try_realloc_raw(old_ptr, old_size, requested_size, align_of::<T>()) {
+        Ok(res) => {
+            record_realloc(old_ptr, old_tracked_size, res, requested_size);
+            *ptr = NonNull::new_unchecked(res as *mut T);
+            Ok(())
         }
-
-        // Most system don't provide an `aligned_realloc`.
-        // If `libc::realloc()` does not give us an aligned pointer,
-        // we have to perform an additional aligned allocation and memcpy over.
-        if res as usize % align_of::<T>() != 0 {
-            let actual_res = malloc_aligned::<T>(requested_size);
-            if actual_res.is_null() {
-                return None;
+        Err(res) => {
+            if res != old_ptr {
+                record_realloc(old_ptr, old_tracked_size, res, old_size);
+                *ptr = NonNull::new_unchecked(res as *mut T);
             }
-
-            // no need to do checked_mul() here since it must be <= `requested_size`.
-            let copy_len = old_count.min(new_count) * size_of::<T>();
-            copy_nonoverlapping(res, actual_res, copy_len);
-            libc::free(res);
-            res = actual_res;
+            Err(AllocError::failed::<T>(requested_size))
         }
+    }
+}
 
-        NonNull::new(res as *mut T)
-    })()
-    .unwrap_or_else(|| handle_alloc_error(Layout::new::<T>()))
+//}}}
+
+//{{{ Allocation hooks ----------------------------------------------------------------------------
+
+#[cfg(feature = "alloc_stats")]
+use crate::stats::tracked_size;
+
+#[cfg(not(feature = "alloc_stats"))]
+fn tracked_size(_: *const c_void) -> usize {
+    0
+}
+
+/// Records a new allocation of `size` bytes made by `mbox`, in the debug registry and the
+/// statistics.
+#[cfg_attr(
+    not(all(feature = "alloc_registry", feature = "alloc_stats")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_alloc(ptr: *const c_void, size: usize) {
+    #[cfg(feature = "alloc_registry")]
+    crate::registry::record_alloc(ptr, size);
+    #[cfg(feature = "alloc_stats")]
+    crate::stats::record_alloc(ptr);
+}
+
+/// Records a pointer being freed, or otherwise no longer owned by `mbox`.
+#[cfg_attr(
+    not(all(feature = "alloc_registry", feature = "alloc_stats")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_free(ptr: *const c_void) {
+    #[cfg(feature = "alloc_registry")]
+    crate::registry::record_free(ptr);
+    #[cfg(feature = "alloc_stats")]
+    crate::stats::record_free(ptr);
+}
+
+/// Records a pointer being adopted by a box, in the debug registry and the statistics.
+#[cfg_attr(
+    not(all(feature = "alloc_registry", feature = "alloc_stats")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_adopt(ptr: *const c_void) {
+    #[cfg(feature = "alloc_registry")]
+    crate::registry::record_adopt(ptr);
+    #[cfg(feature = "alloc_stats")]
+    crate::stats::record_adopt(ptr);
+}
+
+/// Records a pointer being released from a box (e.g. by `MBox::into_raw()`), while the memory
+/// itself is still alive.
+#[cfg_attr(
+    not(all(feature = "alloc_registry", feature = "alloc_stats")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_release(ptr: *const c_void) {
+    #[cfg(feature = "alloc_registry")]
+    crate::registry::record_release(ptr);
+    #[cfg(feature = "alloc_stats")]
+    crate::stats::record_release(ptr);
+}
+
+/// Records an allocation being moved from `old_ptr` to `new_ptr` with `size` bytes by
+/// `realloc()`. The `old_size` must be obtained from `tracked_size(old_ptr)` before reallocating.
+#[cfg_attr(
+    not(all(feature = "alloc_registry", feature = "alloc_stats")),
+    allow(unused_variables)
+)]
+fn record_realloc(old_ptr: *const c_void, old_size: usize, new_ptr: *const c_void, size: usize) {
+    #[cfg(feature = "alloc_registry")]
+    crate::registry::record_realloc(old_ptr, new_ptr, size);
+    #[cfg(feature = "alloc_stats")]
+    crate::stats::record_realloc(old_ptr, old_size, new_ptr);
+}
+
+//}}}
+
+//{{{ Fault injection hooks -----------------------------------------------------------------------
+
+#[cfg(feature = "fault_injection")]
+use crate::fault::should_fail;
+
+#[cfg(not(feature = "fault_injection"))]
+fn should_fail(_: usize) -> bool {
+    false
+}
+
+/// Fails the allocation of `requested_size` bytes if a fault has been injected on the current
+/// thread.
+fn inject_fault(requested_size: usize, align: usize) -> Result<(), AllocError> {
+    if should_fail(requested_size) {
+        Err(AllocError::failed_aligned(requested_size, align))
+    } else {
+        Ok(())
+    }
+}
+
+//}}}
+
+//{{{ Pointer checks ------------------------------------------------------------------------------
+
+/// Panics if a pointer about to be adopted is null.
+#[cfg(feature = "check_pointers")]
+pub(crate) fn check_non_null<T: ?Sized>(ptr: *const T) {
+    assert!(!ptr.is_null(), "mbox: cannot adopt a null pointer");
+}
+
+/// Panics if a pointer about to be adopted is not aligned to `align` bytes.
+#[cfg(feature = "check_pointers")]
+pub(crate) fn check_aligned<T: ?Sized>(ptr: *const T, align: usize) {
+    let ptr = ptr as *const c_void;
+    assert!(
+        ptr as usize % align == 0,
+        "mbox: cannot adopt {:p}, which is not aligned to {} bytes",
+        ptr,
+        align
+    );
 }
 
 //}}}
@@ -253,6 +741,27 @@ impl Drop for DropCounter {
     }
 }
 
+/// A test deallocator to count how many times a pointer has been freed through it.
+#[cfg(all(test, not(windows)))]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeallocCounter(SharedCounter);
+
+#[cfg(all(test, not(windows)))]
+impl std::ops::Deref for DeallocCounter {
+    type Target = SharedCounter;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(all(test, not(windows)))]
+impl crate::free::Dealloc for DeallocCounter {
+    unsafe fn dealloc(&self, ptr: NonNull<c_void>) {
+        self.0.inc();
+        gen_free(ptr);
+    }
+}
+
 //}}}
 
 //{{{ Panic-on-clone ------------------------------------------------------------------------------
@@ -64,11 +64,13 @@
 //!
 //! ## Usage
 //!
-//! This crate provides three main types, all of which uses the system's `malloc`/`free` as the
+//! This crate provides five main types, all of which uses the system's `malloc`/`free` as the
 //! allocator.
 //!
 //! * `MBox<T>` — Similar to `Box<T>`.
+//! * `MVec<T>` — Similar to `Vec<T>`.
 //! * `MString` — Similar to `std::ffi::CString`.
+//! * `MCString` — Like `MString`, but without requiring the content to be UTF-8.
 //! * `MArray<T>` — A null-terminated array, which can be used to represent e.g. array of C strings
 //!   terminated by a null pointer.
 //!
@@ -87,18 +89,83 @@
 //!
 //! ### Nightly
 //!
-//! To use nightly-channel features (if you need support for custom dynamic-sized types), enable the
-//! `nightly` feature:
+//! To use nightly-channel features (if you need support for custom dynamic-sized types, or the
+//! `MallocAlloc` allocator to convert `Vec<T, MallocAlloc>` into `MBox<[T]>` without copying), enable
+//! the `nightly` feature:
 //!
 //! ```toml
 //! [dependencies]
 //! mbox = { version = "0.7", features = ["nightly"] }
 //! ```
 //!
+//! The `nightly` feature also makes `MBox::<[T]>::from_slice()`, `MBox<[T]>::clone()`,
+//! `MArray::from_slice()` and `MVec::extend_from_slice()` copy slices of primitive types and raw
+//! pointers using a single `memcpy()`. On stable, these clone the items one by one, so call the
+//! `*_copy_slice` variants (e.g. `MBox::from_copy_slice()`) instead to get the `memcpy()`. They
+//! accept every type implementing `mbox::BitCopy`.
+//!
+//! ### Global allocator
+//!
+//! `mbox::Malloc` can be registered as the `#[global_allocator]` to make every Rust heap allocation
+//! `free()`-compatible. Enabling the `global_malloc` feature then provides `unsafe` zero-copy
+//! conversions between `Box<T>`, `Vec<T>`, `String` and `MBox<T>`, `MBox<[T]>`, `MBox<str>`:
+//!
+//! ```toml
+//! [dependencies]
+//! mbox = { version = "0.7", features = ["global_malloc"] }
+//! ```
+//!
+//! ### Leak tracking
+//!
+//! Enabling the `alloc_registry` feature records every allocation owned by `mbox` together with
+//! its backtrace. `mbox::registry::assert_no_leaks()` and `mbox::registry::dump()` can then be used
+//! to find the allocations which were never freed, e.g. pointers released to C with
+//! `MBox::into_raw()` which never came back. The `check_pointers` feature further validates
+//! every pointer adopted by `from_raw()` functions and freed by `mbox`, panicking on null,
+//! misaligned, doubly-adopted or unknown pointers.
+//!
+//! ```toml
+//! [dependencies]
+//! mbox = { version = "0.7", features = ["alloc_registry"] }
+//! ```
+//!
+//! These two features require Rust 1.66, while the rest of the crate supports Rust 1.36.
+//!
+//! ### Testing allocation failures
+//!
+//! Enabling the `fault_injection` feature (e.g. in `[dev-dependencies]`) allows tests to make the
+//! allocations on the current thread fail on demand using `mbox::fault::inject()`, to exercise the
+//! `try_*` error paths and `handle_alloc_error()`.
+//!
+//! ### Allocation statistics
+//!
+//! Enabling the `alloc_stats` feature maintains global counters of the total allocations made by
+//! `mbox`, and of the live and peak memory owned by `mbox` (including pointers adopted from C),
+//! readable with `mbox::stats::snapshot()`. Callbacks installed by
+//! `mbox::stats::set_alloc_hook()` and `set_free_hook()` can export every allocation to a metrics
+//! system. This feature does not require `std`.
+//!
+//! ```toml
+//! [dependencies]
+//! mbox = { version = "0.7", features = ["alloc_stats"] }
+//! ```
+//!
+//! ### Heap introspection
+//!
+//! On Linux with glibc, enabling the `heap_info` feature provides the `mbox::heap` module, which
+//! wraps `mallinfo2()`, `malloc_trim()`, `malloc_stats()` and `malloc_info()` to inspect the heap
+//! which the memory owned by `mbox` lives in, e.g. to diagnose fragmentation.
+//!
+//! ```toml
+//! [dependencies]
+//! mbox = { version = "0.7", features = ["heap_info"] }
+//! ```
+//!
 //! ## Migrating from other crates
 //!
-//! Note that `MBox` does not support custom allocator. If the type requires custom allocation,
-//! `MBox` cannot serve you.
+//! Note that `MBox` only supports `malloc`-family allocators. Memory which must be released by a
+//! library-specific function (e.g. `g_free()`) can be owned using `MBox<T, D>` with a custom
+//! deallocator `D` (see `free::Dealloc`).
 //!
 //! * [`malloc_buf`](https://crates.io/crates/malloc_buf) — `Malloc<T>` is equivalent to `MBox<T>`.
 //!   Note however that `MBox<[T]>::from_raw_parts` will not allow null, 0-length buffers; use a
@@ -120,20 +187,49 @@
 
 #![cfg_attr(
     feature = "nightly",
-    feature(min_specialization, unsize, coerce_unsized)
+    feature(min_specialization, unsize, coerce_unsized, allocator_api)
+)]
+#![cfg_attr(
+    all(test, feature = "nightly", feature = "fault_injection"),
+    feature(alloc_error_hook)
 )]
 #![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(not(feature = "std"))]
+extern crate alloc;
 #[cfg(not(feature = "std"))]
 extern crate core as std;
 extern crate libc;
 #[cfg(feature = "stable_deref_trait")]
 extern crate stable_deref_trait;
 
+#[cfg(feature = "fault_injection")]
+pub mod fault;
 pub mod free;
+pub mod global;
+#[cfg(all(feature = "heap_info", target_os = "linux", target_env = "gnu"))]
+pub mod heap;
 mod internal;
 pub mod mbox;
+#[cfg(feature = "alloc_registry")]
+pub mod registry;
 pub mod sentinel;
+#[cfg(feature = "alloc_stats")]
+pub mod stats;
+pub mod vec;
+
+pub use self::global::Malloc;
+#[cfg(feature = "nightly")]
+pub use self::global::MallocAlloc;
+pub use self::internal::AllocError;
+pub use self::mbox::{FromUtf8Error, MBox, MHandle};
+pub use self::sentinel::{
+    IntoStringError, MArray, MArrayRef, MCString, MStr, MString, MStringBuf, NulError,
+};
+pub use self::vec::{BitCopy, MVec};
 
-pub use self::mbox::MBox;
-pub use self::sentinel::{MArray, MString};
+// the conversions enabled by `global_malloc` are only sound with this allocator. It is declared
+// here since Rust 1.36 does not allow `#[global_allocator]` in a submodule.
+#[cfg(all(test, feature = "global_malloc"))]
+#[global_allocator]
+static GLOBAL: Malloc = Malloc;
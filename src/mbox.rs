@@ -1,5 +1,7 @@
 //! `malloc`-based Box.
 
+use libc::c_void;
+
 #[cfg(feature = "stable_deref_trait")]
 use stable_deref_trait::StableDeref;
 
@@ -9,10 +11,10 @@ use std::fmt::{Debug, Display, Formatter, Pointer, Result as FormatResult};
 use std::hash::{Hash, Hasher};
 use std::iter::{DoubleEndedIterator, FromIterator, IntoIterator};
 use std::marker::Unpin;
-use std::mem::{forget, MaybeUninit};
-use std::ops::{Deref, DerefMut};
+use std::mem::{size_of, ManuallyDrop, MaybeUninit};
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
 use std::pin::Pin;
-use std::ptr::{copy_nonoverlapping, drop_in_place, read, write};
+use std::ptr::{copy, copy_nonoverlapping, drop_in_place, read, write};
 use std::slice::{Iter, IterMut};
 use std::str::{from_utf8, Utf8Error};
 use std::{
@@ -20,28 +22,54 @@ use std::{
     ptr::NonNull,
 };
 
-use crate::internal::{gen_free, gen_malloc, gen_realloc, Unique};
+#[cfg(all(feature = "global_malloc", not(feature = "std")))]
+use alloc::string::String;
+#[cfg(all(
+    any(feature = "global_malloc", feature = "nightly"),
+    not(feature = "std")
+))]
+use alloc::{boxed::Box, vec::Vec};
+
+#[cfg(target_os = "linux")]
+use crate::internal::{advise_huge_pages, gen_usable_size, HUGE_PAGE_SIZE};
+#[cfg(feature = "check_pointers")]
+use crate::internal::{check_aligned, check_non_null};
+use crate::internal::{
+    gen_malloc, gen_realloc, try_gen_calloc, try_gen_calloc_aligned, try_gen_malloc,
+    try_gen_malloc_aligned, usable_count, AllocError, Unique, MIN_MALLOC_ALIGN,
+};
 
-#[cfg(all(test, not(windows)))]
-use crate::internal::DropCounter;
 #[cfg(test)]
-use crate::internal::PanicOnClone;
+use crate::internal::{gen_free, PanicOnClone};
+#[cfg(all(test, not(windows)))]
+use crate::internal::{DeallocCounter, DropCounter};
 #[cfg(test)]
 use std::iter::{once, repeat};
-#[cfg(all(test, not(windows)))]
-use std::mem::size_of;
+#[cfg(test)]
+use std::mem::align_of;
+#[cfg(test)]
+use std::ptr::null_mut;
 
+#[cfg(feature = "nightly")]
+use crate::global::MallocAlloc;
 #[cfg(feature = "nightly")]
 use std::marker::Unsize;
 #[cfg(feature = "nightly")]
 use std::ops::CoerceUnsized;
 
-use crate::free::Free;
+#[cfg(all(test, feature = "std"))]
+use crate::free::AssertThreadSafe;
+#[cfg(test)]
+use crate::free::FnFree;
+use crate::free::{Dealloc, Destructor, Free, LibcFree, SendDestructor, SyncDestructor};
+use crate::vec::{BitCopy, MVec};
 
 //{{{ Basic structure -----------------------------------------------------------------------------
 
 /// A malloc-backed box. This structure allows Rust to exchange objects with C without cloning.
-pub struct MBox<T: ?Sized + Free>(Unique<T>);
+///
+/// The memory is released using the deallocator `D`, which is libc's `free()` by default.
+pub struct MBox<T: ?Sized + Free, D: Dealloc = LibcFree>(Unique<T>, D);
 
 impl<T: ?Sized + Free> MBox<T> {
     /// Constructs a new malloc-backed box from a pointer allocated by `malloc`.
@@ -57,7 +85,7 @@ impl<T: ?Sized + Free> MBox<T> {
     /// `free()`. Therefore, you must not use a conceived dangling pointer such as `NonNull::dangling()`
     /// here. Consider using `malloc(1)` in case of ZSTs.
     pub unsafe fn from_raw(ptr: *mut T) -> Self {
-        Self::from_non_null_raw(NonNull::new_unchecked(ptr))
+        Self::from_raw_in(ptr, LibcFree)
     }
 
     /// Constructs a new malloc-backed box from a non-null pointer allocated by `malloc`.
@@ -73,7 +101,47 @@ impl<T: ?Sized + Free> MBox<T> {
     /// `free()`. Therefore, you must not use a conceived dangling pointer such as `NonNull::dangling()`
     /// here. Consider using `malloc(1)` in case of ZSTs.
     pub unsafe fn from_non_null_raw(ptr: NonNull<T>) -> Self {
-        Self(Unique::new(ptr))
+        Self::from_non_null_raw_in(ptr, LibcFree)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T: ?Sized + Free> MBox<T> {
+    /// Returns the number of bytes which can actually be used in the allocation, as reported by
+    /// `malloc_usable_size()`. This is often larger than the requested size.
+    pub fn usable_size(boxed: &Self) -> usize {
+        // SAFETY: the pointer is obtained from `malloc()`.
+        unsafe { gen_usable_size(boxed.0.as_non_null_ptr().cast::<u8>()) }
+    }
+}
+
+impl<T: ?Sized + Free, D: Dealloc> MBox<T, D> {
+    /// Constructs a new box from a pointer, which will be released using the deallocator `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be allocated by the allocator corresponding to `dealloc`. It must be aligned
+    /// and not null. The content of the pointer must be already initialized. The pointer's
+    /// ownership is passed into the box, and thus should not be used after this function returns.
+    pub unsafe fn from_raw_in(ptr: *mut T, dealloc: D) -> Self {
+        #[cfg(feature = "check_pointers")]
+        check_non_null(ptr);
+        Self::from_non_null_raw_in(NonNull::new_unchecked(ptr), dealloc)
+    }
+
+    /// Constructs a new box from a non-null pointer, which will be released using the deallocator
+    /// `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be allocated by the allocator corresponding to `dealloc`. It must be aligned.
+    /// The content of the pointer must be already initialized. The pointer's ownership is passed
+    /// into the box, and thus should not be used after this function returns.
+    pub unsafe fn from_non_null_raw_in(ptr: NonNull<T>, dealloc: D) -> Self {
+        #[cfg(feature = "check_pointers")]
+        check_aligned(ptr.as_ptr(), T::align_of_raw(ptr));
+        dealloc.adopt(ptr.cast());
+        Self(Unique::new(ptr), dealloc)
     }
 
     /// Obtains the pointer owned by the box.
@@ -86,31 +154,59 @@ impl<T: ?Sized + Free> MBox<T> {
         boxed.0.as_non_null_ptr().as_ptr()
     }
 
-    /// Consumes the box and returns the original pointer.
+    /// Obtains a reference to the deallocator of the box.
+    pub fn dealloc(boxed: &Self) -> &D {
+        &boxed.1
+    }
+
+    /// Consumes the box and returns the original pointer. The deallocator is dropped.
     ///
     /// The caller is responsible for `free`ing the pointer after this.
     pub fn into_raw(boxed: Self) -> *mut T {
         Self::into_non_null_raw(boxed).as_ptr()
     }
 
-    /// Consumes the box and returns the original non-null pointer.
+    /// Consumes the box and returns the original non-null pointer. The deallocator is dropped.
     ///
     /// The caller is responsible for `free`ing the pointer after this.
     pub fn into_non_null_raw(boxed: Self) -> NonNull<T> {
-        let ptr = boxed.0.as_non_null_ptr();
-        forget(boxed);
-        ptr
+        Self::into_non_null_raw_with_dealloc(boxed).0
+    }
+
+    /// Consumes the box and returns the original pointer together with the deallocator.
+    ///
+    /// The caller is responsible for releasing the pointer using the deallocator after this.
+    pub fn into_raw_with_dealloc(boxed: Self) -> (*mut T, D) {
+        let (ptr, dealloc) = Self::into_non_null_raw_with_dealloc(boxed);
+        (ptr.as_ptr(), dealloc)
+    }
+
+    /// Consumes the box and returns the original non-null pointer together with the deallocator.
+    ///
+    /// The caller is responsible for releasing the pointer using the deallocator after this.
+    pub fn into_non_null_raw_with_dealloc(boxed: Self) -> (NonNull<T>, D) {
+        let (ptr, dealloc) = Self::into_owned_raw(boxed);
+        dealloc.release(ptr.cast());
+        (ptr, dealloc)
+    }
+
+    /// Consumes the box like `into_non_null_raw_with_dealloc()`, but without telling the
+    /// deallocator that the pointer is released. Used when the pointer is freed right away.
+    fn into_owned_raw(boxed: Self) -> (NonNull<T>, D) {
+        let boxed = ManuallyDrop::new(boxed);
+        // SAFETY: the box is never used again after moving the deallocator out.
+        unsafe { (boxed.0.as_non_null_ptr(), read(&boxed.1)) }
     }
 }
 
-impl<T: ?Sized + Free> Drop for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> Drop for MBox<T, D> {
     fn drop(&mut self) {
-        // SAFETY: the pointer is assumed to be obtained from `malloc()`.
-        unsafe { T::free(self.0.as_non_null_ptr()) };
+        // SAFETY: the pointer is assumed to be obtained from the allocator matching `D`.
+        unsafe { self.1.drop_and_dealloc(self.0.as_non_null_ptr()) };
     }
 }
 
-impl<T: ?Sized + Free> Deref for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> Deref for MBox<T, D> {
     type Target = T;
     fn deref(&self) -> &T {
         unsafe { &*Self::as_ptr(self) }
@@ -118,82 +214,97 @@ impl<T: ?Sized + Free> Deref for MBox<T> {
 }
 
 #[cfg(feature = "stable_deref_trait")]
-unsafe impl<T: ?Sized + Free> StableDeref for MBox<T> {}
+unsafe impl<T: ?Sized + Free, D: Dealloc> StableDeref for MBox<T, D> {}
 
-impl<T: ?Sized + Free> Unpin for MBox<T> {}
+impl<T: ?Sized + Free, D: Dealloc> Unpin for MBox<T, D> {}
 
-impl<T: ?Sized + Free> DerefMut for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> DerefMut for MBox<T, D> {
     fn deref_mut(&mut self) -> &mut T {
         unsafe { &mut *Self::as_mut_ptr(self) }
     }
 }
 
-impl<T: ?Sized + Free> AsRef<T> for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> AsRef<T> for MBox<T, D> {
     fn as_ref(&self) -> &T {
         self
     }
 }
 
-impl<T: ?Sized + Free> AsMut<T> for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> AsMut<T> for MBox<T, D> {
     fn as_mut(&mut self) -> &mut T {
         self
     }
 }
 
-impl<T: ?Sized + Free> Borrow<T> for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> Borrow<T> for MBox<T, D> {
     fn borrow(&self) -> &T {
         self
     }
 }
 
-impl<T: ?Sized + Free> BorrowMut<T> for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> BorrowMut<T> for MBox<T, D> {
     fn borrow_mut(&mut self) -> &mut T {
         self
     }
 }
 
 #[cfg(feature = "nightly")]
-impl<T: ?Sized + Free + Unsize<U>, U: ?Sized + Free> CoerceUnsized<MBox<U>> for MBox<T> {}
+impl<T: ?Sized + Free + Unsize<U>, U: ?Sized + Free, D: Dealloc> CoerceUnsized<MBox<U, D>>
+    for MBox<T, D>
+{
+}
 
-impl<T: ?Sized + Free> Pointer for MBox<T> {
+impl<T: ?Sized + Free, D: Dealloc> Pointer for MBox<T, D> {
     fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
         Pointer::fmt(&Self::as_ptr(self), formatter)
     }
 }
 
-impl<T: ?Sized + Free + Debug> Debug for MBox<T> {
+impl<T: ?Sized + Free + Debug, D: Dealloc> Debug for MBox<T, D> {
     fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
         self.deref().fmt(formatter)
     }
 }
 
-impl<T: ?Sized + Free + Display> Display for MBox<T> {
+impl<T: ?Sized + Free + Display, D: Dealloc> Display for MBox<T, D> {
     fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
         self.deref().fmt(formatter)
     }
 }
 
-impl<T: ?Sized + Free + Hash> Hash for MBox<T> {
+impl<T: ?Sized + Free + Hash, D: Dealloc> Hash for MBox<T, D> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.deref().hash(state)
     }
 }
 
-impl<U: ?Sized + Free, T: ?Sized + Free + PartialEq<U>> PartialEq<MBox<U>> for MBox<T> {
-    fn eq(&self, other: &MBox<U>) -> bool {
+impl<U, T, D1, D2> PartialEq<MBox<U, D2>> for MBox<T, D1>
+where
+    U: ?Sized + Free,
+    T: ?Sized + Free + PartialEq<U>,
+    D1: Dealloc,
+    D2: Dealloc,
+{
+    fn eq(&self, other: &MBox<U, D2>) -> bool {
         self.deref().eq(other.deref())
     }
 }
 
-impl<T: ?Sized + Free + Eq> Eq for MBox<T> {}
+impl<T: ?Sized + Free + Eq, D: Dealloc> Eq for MBox<T, D> {}
 
-impl<U: ?Sized + Free, T: ?Sized + Free + PartialOrd<U>> PartialOrd<MBox<U>> for MBox<T> {
-    fn partial_cmp(&self, other: &MBox<U>) -> Option<Ordering> {
+impl<U, T, D1, D2> PartialOrd<MBox<U, D2>> for MBox<T, D1>
+where
+    U: ?Sized + Free,
+    T: ?Sized + Free + PartialOrd<U>,
+    D1: Dealloc,
+    D2: Dealloc,
+{
+    fn partial_cmp(&self, other: &MBox<U, D2>) -> Option<Ordering> {
         self.deref().partial_cmp(other.deref())
     }
 }
 
-impl<T: ?Sized + Free + Ord> Ord for MBox<T> {
+impl<T: ?Sized + Free + Ord, D: Dealloc> Ord for MBox<T, D> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.deref().cmp(other.deref())
     }
@@ -206,20 +317,47 @@ impl<T: ?Sized + Free + Ord> Ord for MBox<T> {
 impl<T> MBox<T> {
     /// Constructs a new malloc-backed box, and move an initialized value into it.
     pub fn new(value: T) -> Self {
-        let storage = gen_malloc(1);
+        Self::try_new(value).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Constructs a new malloc-backed box, and move an initialized value into it. Returns an
+    /// `AllocError` if the memory cannot be allocated.
+    pub fn try_new(value: T) -> Result<Self, AllocError> {
+        let storage = try_gen_malloc(1)?;
         // SAFETY: the `storage` is uninitialized and enough to store T.
         // this pointer is obtained via `malloc` and thus good for `from_raw`.
         unsafe {
             write(storage.as_ptr(), value);
-            Self::from_non_null_raw(storage)
+            Ok(Self::from_non_null_raw(storage))
         }
     }
 
     /// Constructs a new malloc-backed box with uninitialized content.
     pub fn new_uninit() -> MBox<MaybeUninit<T>> {
-        let storage = gen_malloc(1);
+        Self::try_new_uninit().unwrap_or_else(|e| e.handle())
+    }
+
+    /// Constructs a new malloc-backed box with uninitialized content. Returns an `AllocError` if
+    /// the memory cannot be allocated.
+    pub fn try_new_uninit() -> Result<MBox<MaybeUninit<T>>, AllocError> {
+        let storage = try_gen_malloc(1)?;
         // SAFETY: The storage is allowed to be uninitialized.
-        unsafe { MBox::from_non_null_raw(storage) }
+        unsafe { Ok(MBox::from_non_null_raw(storage)) }
+    }
+
+    /// Constructs a new malloc-backed box with the content filled with zeros.
+    ///
+    /// The memory is allocated using `calloc()` when the alignment of `T` permits.
+    pub fn new_zeroed() -> MBox<MaybeUninit<T>> {
+        Self::try_new_zeroed().unwrap_or_else(|e| e.handle())
+    }
+
+    /// Constructs a new malloc-backed box with the content filled with zeros. Returns an
+    /// `AllocError` if the memory cannot be allocated.
+    pub fn try_new_zeroed() -> Result<MBox<MaybeUninit<T>>, AllocError> {
+        let storage = try_gen_calloc(1)?;
+        // SAFETY: The storage is allowed to be uninitialized, let alone zeroed.
+        unsafe { Ok(MBox::from_non_null_raw(storage)) }
     }
 
     /// Constructs a new `Pin<MBox<T>>`. If `T` does not implement `Unpin`, then `value` will be
@@ -227,27 +365,30 @@ impl<T> MBox<T> {
     pub fn pin(value: T) -> Pin<Self> {
         Self::into_pin(Self::new(value))
     }
+}
 
+impl<T, D: Dealloc> MBox<T, D> {
     /// Converts an `MBox<T>` into a single-item `MBox<[T]>`.
     ///
     /// This conversion does not allocate on the heap and happens in place.
-    pub fn into_boxed_slice(boxed: Self) -> MBox<[T]> {
+    pub fn into_boxed_slice(boxed: Self) -> MBox<[T], D> {
+        let (ptr, dealloc) = Self::into_non_null_raw_with_dealloc(boxed);
         // SAFETY: free() only cares about the allocated size, and `T` and
         // `[T; 1]` are equivalent in terms of drop() and free().
-        unsafe { MBox::from_raw_parts(Self::into_raw(boxed), 1) }
+        unsafe { MBox::from_raw_parts_in(ptr.as_ptr(), 1, dealloc) }
     }
 
     /// Consumes the `MBox`, returning the wrapped value.
     pub fn into_inner(boxed: Self) -> T {
         let mut dst = MaybeUninit::uninit();
-        let src = Self::into_non_null_raw(boxed);
-        // SAFETY: after calling `into_raw` above, we have the entire ownership of the malloc'ed
+        let (src, dealloc) = Self::into_owned_raw(boxed);
+        // SAFETY: after calling `into_owned_raw` above, we have the entire ownership of the malloc'ed
         // pointer `src`. The content is moved into the destination. After that, we can free `src`
         // without touching the content. So there is a single copy of the content fully initialized
         // into `dst` which is safe to assume_init.
         unsafe {
             copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), 1);
-            gen_free(src);
+            dealloc.dealloc(src.cast());
             dst.assume_init()
         }
     }
@@ -272,14 +413,15 @@ impl<T> MBox<T> {
     }
 }
 
-impl<T> MBox<MaybeUninit<T>> {
+impl<T, D: Dealloc> MBox<MaybeUninit<T>, D> {
     /// Converts into an initialized box.
     ///
     /// # Safety
     ///
     /// The caller should guarantee `*self` is indeed initialized.
-    pub unsafe fn assume_init(self) -> MBox<T> {
-        MBox::from_non_null_raw(Self::into_non_null_raw(self).cast())
+    pub unsafe fn assume_init(self) -> MBox<T, D> {
+        let (ptr, dealloc) = Self::into_non_null_raw_with_dealloc(self);
+        MBox::from_non_null_raw_in(ptr.cast(), dealloc)
     }
 }
 
@@ -415,6 +557,33 @@ fn test_standard_traits() {
     assert_eq!(MBox::<u8>::default(), MBox::new(0u8));
 }
 
+#[test]
+fn test_try_new() {
+    let a = MBox::try_new(7u8).unwrap();
+    assert_eq!(*a, 7);
+
+    let b = MBox::<u8>::try_new_uninit().unwrap();
+    let b = unsafe {
+        write(MBox::as_ptr(&b) as *mut u8, 9);
+        b.assume_init()
+    };
+    assert_eq!(*b, 9);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_new_zeroed() {
+    let a = unsafe { MBox::<u64>::new_zeroed().assume_init() };
+    assert_eq!(*a, 0);
+
+    #[repr(C, align(4096))]
+    struct A([u8; 8192]);
+
+    let b = unsafe { MBox::<A>::new_zeroed().assume_init() };
+    assert_eq!(MBox::as_ptr(&b) as usize % 4096, 0);
+    assert!(b.deref().0.iter().all(|x| *x == 0));
+}
+
 #[test]
 fn test_zero_sized_type() {
     let a = MBox::new(());
@@ -423,6 +592,7 @@ fn test_zero_sized_type() {
 
 #[cfg(not(windows))]
 #[test]
+#[allow(clippy::nonminimal_bool)]
 fn test_non_zero() {
     let b = 0u64;
     assert!(!Some(MBox::new(0u64)).is_none());
@@ -465,71 +635,6 @@ fn test_aligned() {
 mod slice_helper {
     use super::*;
 
-    /// A `Vec`-like structure backed by `malloc()`.
-    pub struct MSliceBuilder<T> {
-        ptr: NonNull<T>,
-        cap: usize,
-        len: usize,
-    }
-
-    impl<T> MSliceBuilder<T> {
-        /// Creates a new slice builder with an initial capacity.
-        pub fn with_capacity(cap: usize) -> MSliceBuilder<T> {
-            MSliceBuilder {
-                ptr: gen_malloc(cap),
-                cap,
-                len: 0,
-            }
-        }
-
-        pub fn push(&mut self, obj: T) {
-            if self.len >= self.cap {
-                let new_cap = (self.cap * 2).max(1);
-                // SAFETY:
-                //  - ptr is initialized from gen_malloc() so it can be placed into gen_realloc()
-                unsafe {
-                    self.ptr = gen_realloc(self.ptr, self.cap, new_cap);
-                }
-                self.cap = new_cap;
-            }
-
-            // SAFETY:
-            //  - we guarantee that `ptr `points to an array of nonzero length `cap`, and
-            //    the `if` condition ensures the invariant `self.len < cap`, so
-            //    `ptr.add(self.len)` is always a valid (but uninitialized) object.
-            //  - since `ptr[self.len]` is not yet initialized, we can `write()` into it safely.
-            unsafe {
-                write(self.ptr.as_ptr().add(self.len), obj);
-            }
-            self.len += 1;
-        }
-
-        pub fn into_mboxed_slice(self) -> MBox<[T]> {
-            // SAFETY: `self.ptr` has been allocated by malloc(), and its length is self.cap
-            // (>= self.len).
-            let slice = unsafe { MBox::from_raw_parts(self.ptr.as_ptr(), self.len) };
-            forget(self);
-            slice
-        }
-    }
-
-    impl<T> MSliceBuilder<MaybeUninit<T>> {
-        /// Sets the length of the builder to the same as the capacity. The elements in the
-        /// uninitialized tail remains uninitialized.
-        pub fn set_len_to_cap(&mut self) {
-            self.len = self.cap;
-        }
-    }
-
-    impl<T> Drop for MSliceBuilder<T> {
-        fn drop(&mut self) {
-            // SAFETY: `ptr` has been allocated by `gen_malloc()`.
-            unsafe {
-                gen_free(self.ptr);
-            }
-        }
-    }
-
     #[repr(C)]
     struct SliceParts<T> {
         ptr: *mut T,
@@ -538,10 +643,7 @@ mod slice_helper {
 
     impl<T> Clone for SliceParts<T> {
         fn clone(&self) -> Self {
-            Self {
-                ptr: self.ptr,
-                len: self.len,
-            }
+            *self
         }
     }
     impl<T> Copy for SliceParts<T> {}
@@ -567,18 +669,77 @@ mod slice_helper {
         let parts = unsafe { SliceTransformer { fat_ptr }.parts };
         (parts.ptr, parts.len)
     }
+
+    /// Converts a range into the `(start, end)` indices of a slice of length `len`. Panics if the
+    /// range is out of bounds.
+    pub fn slice_range<R: RangeBounds<usize>>(range: R, len: usize) -> (usize, usize) {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n
+                .checked_add(1)
+                .expect("attempted to index slice from after maximum usize"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n
+                .checked_add(1)
+                .expect("attempted to index slice up to maximum usize"),
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => len,
+        };
+        assert!(
+            start <= end,
+            "slice index starts at {} but ends at {}",
+            start,
+            end
+        );
+        assert!(
+            end <= len,
+            "range end index {} out of range for slice of length {}",
+            end,
+            len
+        );
+        (start, end)
+    }
+
+    /// Moves the `tail_len` items starting at index `tail_start` to index `dest`, then shrinks
+    /// the allocation to fit exactly `dest + tail_len` items. Returns the reallocated pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be allocated by `malloc()` with at least `tail_start + tail_len` items, and
+    /// `dest <= tail_start`. The items in `dest..tail_start` must have been moved out or dropped.
+    pub unsafe fn close_gap<T>(
+        ptr: NonNull<T>,
+        dest: usize,
+        tail_start: usize,
+        tail_len: usize,
+    ) -> NonNull<T> {
+        if dest == tail_start {
+            return ptr;
+        }
+        copy(
+            ptr.as_ptr().add(tail_start),
+            ptr.as_ptr().add(dest),
+            tail_len,
+        );
+        gen_realloc(ptr, tail_start + tail_len, dest + tail_len)
+    }
 }
 
-use self::slice_helper::{slice_from_raw_parts_mut, slice_into_raw_parts_mut, MSliceBuilder};
+use self::slice_helper::{
+    close_gap, slice_from_raw_parts_mut, slice_into_raw_parts_mut, slice_range,
+};
 
 /// The iterator returned from `MBox<[T]>::into_iter()`.
-pub struct MSliceIntoIter<T> {
+pub struct MSliceIntoIter<T, D: Dealloc = LibcFree> {
     ptr: NonNull<T>,
     begin: usize,
     end: usize,
+    dealloc: D,
 }
 
-impl<T> Iterator for MSliceIntoIter<T> {
+impl<T, D: Dealloc> Iterator for MSliceIntoIter<T, D> {
     type Item = T;
 
     fn next(&mut self) -> Option<T> {
@@ -599,7 +760,7 @@ impl<T> Iterator for MSliceIntoIter<T> {
     }
 }
 
-impl<T> DoubleEndedIterator for MSliceIntoIter<T> {
+impl<T, D: Dealloc> DoubleEndedIterator for MSliceIntoIter<T, D> {
     fn next_back(&mut self) -> Option<T> {
         if self.begin == self.end {
             None
@@ -613,19 +774,169 @@ impl<T> DoubleEndedIterator for MSliceIntoIter<T> {
     }
 }
 
-unsafe impl<T: Send> Send for MSliceIntoIter<T> {}
-unsafe impl<T: Sync> Sync for MSliceIntoIter<T> {}
+unsafe impl<T: Send, D: Dealloc + Send> Send for MSliceIntoIter<T, D> {}
+unsafe impl<T: Sync, D: Dealloc + Sync> Sync for MSliceIntoIter<T, D> {}
 
-impl<T> ExactSizeIterator for MSliceIntoIter<T> {}
+impl<T, D: Dealloc> ExactSizeIterator for MSliceIntoIter<T, D> {}
 
-impl<T> Drop for MSliceIntoIter<T> {
+impl<T, D: Dealloc> Drop for MSliceIntoIter<T, D> {
     fn drop(&mut self) {
         unsafe {
             let base = self.ptr.as_ptr().add(self.begin);
             let len = self.end - self.begin;
             let slice = slice_from_raw_parts_mut(base, len);
             drop_in_place(slice);
-            gen_free(self.ptr);
+            self.dealloc.dealloc(self.ptr.cast());
+        }
+    }
+}
+
+/// The iterator returned from `MBox<[T]>::drain()`.
+///
+/// When dropped, the remaining drained items are dropped, and the tail of the slice is moved
+/// back to fill the hole, shrinking the allocation with `realloc()`.
+pub struct MSliceDrain<'a, T> {
+    // The length of `slice` is temporarily set to the start of the drained range. The items from
+    // `begin` to `end` are owned by this iterator.
+    slice: &'a mut MBox<[T]>,
+    begin: usize,
+    end: usize,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<'a, T> MSliceDrain<'a, T> {
+    fn base(&self) -> *mut T {
+        MBox::as_ptr(self.slice) as *mut T
+    }
+
+    /// Returns the remaining items of this iterator as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: the items from `begin` to `end` are initialized and owned by the iterator.
+        unsafe { &*slice_from_raw_parts_mut(self.base().add(self.begin), self.end - self.begin) }
+    }
+}
+
+impl<'a, T> Iterator for MSliceDrain<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.begin == self.end {
+            None
+        } else {
+            unsafe {
+                let ptr = self.base().add(self.begin);
+                self.begin += 1;
+                Some(read(ptr))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.begin;
+        (len, Some(len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for MSliceDrain<'a, T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.begin == self.end {
+            None
+        } else {
+            unsafe {
+                self.end -= 1;
+                let ptr = self.base().add(self.end);
+                Some(read(ptr))
+            }
+        }
+    }
+}
+
+impl<'a, T> ExactSizeIterator for MSliceDrain<'a, T> {}
+
+impl<'a, T> Drop for MSliceDrain<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            let base = self.base();
+            drop_in_place(slice_from_raw_parts_mut(
+                base.add(self.begin),
+                self.end - self.begin,
+            ));
+            let dest = self.slice.len();
+            let ptr = close_gap(
+                NonNull::new_unchecked(base),
+                dest,
+                self.tail_start,
+                self.tail_len,
+            );
+            self.slice.set_raw_parts(ptr.as_ptr(), dest + self.tail_len);
+        }
+    }
+}
+
+/// The iterator returned from `MBox<str>::drain()`.
+///
+/// When dropped, the tail of the string is moved back to fill the hole, shrinking the allocation
+/// with `realloc()`.
+pub struct MStrDrain<'a> {
+    // The length of `string` is temporarily set to the start of the drained range.
+    string: &'a mut MBox<str>,
+    begin: usize,
+    end: usize,
+    tail_start: usize,
+    tail_len: usize,
+}
+
+impl<'a> MStrDrain<'a> {
+    fn base(&self) -> *mut u8 {
+        MBox::as_ptr(self.string) as *mut u8
+    }
+
+    /// Returns the remaining characters of this iterator as a string slice.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: the bytes from `begin` to `end` are a valid UTF-8 substring of the original
+        // string, which has not been touched yet.
+        unsafe {
+            &*(slice_from_raw_parts_mut(self.base().add(self.begin), self.end - self.begin)
+                as *mut str)
+        }
+    }
+}
+
+impl<'a> Iterator for MStrDrain<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.as_str().chars().next()?;
+        self.begin += c.len_utf8();
+        Some(c)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.as_str().chars().size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for MStrDrain<'a> {
+    fn next_back(&mut self) -> Option<char> {
+        let c = self.as_str().chars().next_back()?;
+        self.end -= c.len_utf8();
+        Some(c)
+    }
+}
+
+impl<'a> Drop for MStrDrain<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            let dest = self.string.len();
+            let ptr = close_gap(
+                NonNull::new_unchecked(self.base()),
+                dest,
+                self.tail_start,
+                self.tail_len,
+            );
+            self.string
+                .set_raw_parts(ptr.as_ptr(), dest + self.tail_len);
         }
     }
 }
@@ -634,6 +945,41 @@ impl<T> Drop for MSliceIntoIter<T> {
 
 //{{{ Slice ---------------------------------------------------------------------------------------
 
+impl<T, D: Dealloc> MBox<[T], D> {
+    /// Constructs a new slice from the pointer and the length (number of items), which will be
+    /// released using the deallocator `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be allocated by the allocator corresponding to `dealloc`. It must be aligned and
+    /// not null.
+    ///
+    /// The allocated size of the pointer must be at least `len * size_of::<T>()`. The content
+    /// must already been initialized.
+    pub unsafe fn from_raw_parts_in(ptr: *mut T, len: usize, dealloc: D) -> Self {
+        Self::from_raw_in(slice_from_raw_parts_mut(ptr, len), dealloc)
+    }
+
+    /// Decomposes the boxed slice into a pointer to the first element and the slice length. The
+    /// deallocator is dropped.
+    pub fn into_raw_parts(self) -> (*mut T, usize) {
+        let (ptr, len, _) = self.into_raw_parts_with_dealloc();
+        (ptr, len)
+    }
+
+    /// Decomposes the boxed slice into a pointer to the first element, the slice length and the
+    /// deallocator.
+    pub fn into_raw_parts_with_dealloc(self) -> (*mut T, usize, D) {
+        let (fat_ptr, dealloc) = Self::into_non_null_raw_with_dealloc(self);
+        let (ptr, len) = slice_into_raw_parts_mut(fat_ptr.as_ptr());
+        (ptr, len, dealloc)
+    }
+}
+
+/// The excess capacity in bytes below which collecting into a boxed slice does not bother
+/// shrinking the allocation, since `malloc()` cannot reuse such a small gap anyway.
+const SHRINK_THRESHOLD: usize = MIN_MALLOC_ALIGN;
+
 impl<T> MBox<[T]> {
     /// Constructs a new malloc-backed slice from the pointer and the length (number of items).
     ///
@@ -644,95 +990,355 @@ impl<T> MBox<[T]> {
     /// The `malloc`ed size of the pointer must be at least `len * size_of::<T>()`. The content
     /// must already been initialized.
     pub unsafe fn from_raw_parts(ptr: *mut T, len: usize) -> Self {
-        Self::from_raw(slice_from_raw_parts_mut(ptr, len))
+        Self::from_raw_parts_in(ptr, len, LibcFree)
+    }
+
+    /// Returns the number of items which fit in the allocation without reallocating, as reported
+    /// by `malloc_usable_size()`. This is often larger than the length.
+    #[cfg(target_os = "linux")]
+    pub fn usable_len(&self) -> usize {
+        match size_of::<T>() {
+            0 => !0,
+            size => MBox::usable_size(self) / size,
+        }
     }
 
     /// Constructs a new boxed slice with uninitialized contents.
     pub fn new_uninit_slice(len: usize) -> MBox<[MaybeUninit<T>]> {
-        let mut builder = MSliceBuilder::with_capacity(len);
-        builder.set_len_to_cap();
-        builder.into_mboxed_slice()
+        Self::try_new_uninit_slice(len).unwrap_or_else(|e| e.handle())
     }
 
-    /// Decomposes the boxed slice into a pointer to the first element and the slice length.
-    pub fn into_raw_parts(mut self) -> (*mut T, usize) {
-        let (ptr, len) = slice_into_raw_parts_mut(Self::as_mut_ptr(&mut self));
-        forget(self);
-        (ptr, len)
+    /// Constructs a new boxed slice with uninitialized contents. Returns an `AllocError` if the
+    /// memory cannot be allocated.
+    pub fn try_new_uninit_slice(len: usize) -> Result<MBox<[MaybeUninit<T>]>, AllocError> {
+        let storage = try_gen_malloc(len)?;
+        // SAFETY: The storage is allowed to be uninitialized.
+        unsafe { Ok(MBox::from_raw_parts(storage.as_ptr(), len)) }
     }
-}
 
-impl<T> MBox<[MaybeUninit<T>]> {
-    /// Converts into an initialized boxed slice.
+    /// Constructs a new boxed slice with the contents filled with zeros.
     ///
-    /// # Safety
+    /// The memory is allocated using `calloc()` when the alignment of `T` permits.
+    pub fn new_zeroed_slice(len: usize) -> MBox<[MaybeUninit<T>]> {
+        Self::try_new_zeroed_slice(len).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Constructs a new boxed slice with the contents filled with zeros. Returns an `AllocError`
+    /// if the memory cannot be allocated.
+    pub fn try_new_zeroed_slice(len: usize) -> Result<MBox<[MaybeUninit<T>]>, AllocError> {
+        let storage = try_gen_calloc(len)?;
+        // SAFETY: The storage is allowed to be uninitialized, let alone zeroed.
+        unsafe { Ok(MBox::from_raw_parts(storage.as_ptr(), len)) }
+    }
+
+    /// Constructs a new boxed slice with uninitialized contents, aligned to at least `align` bytes.
     ///
-    /// The caller should guarantee `*self` is indeed initialized.
-    pub unsafe fn assume_init(self) -> MBox<[T]> {
-        MBox::from_raw(Self::into_raw(self) as *mut [T])
+    /// This is useful for buffers requiring page or sector alignment (e.g. `O_DIRECT` I/O). The
+    /// memory can still be released with plain `free()`.
+    ///
+    /// The alignment is not remembered. Growing the slice afterwards (e.g. by `push()`, `resize()`
+    /// or `extend_from_slice()`) may reallocate it, after which it is only aligned to
+    /// `align_of::<T>()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of 2.
+    pub fn new_uninit_slice_aligned(len: usize, align: usize) -> MBox<[MaybeUninit<T>]> {
+        Self::try_new_uninit_slice_aligned(len, align).unwrap_or_else(|e| e.handle())
     }
-}
 
-impl<T> Default for MBox<[T]> {
-    fn default() -> Self {
-        unsafe { Self::from_raw_parts(gen_malloc(0).as_ptr(), 0) }
+    /// Constructs a new boxed slice with uninitialized contents, aligned to at least `align` bytes.
+    /// Returns an `AllocError` if the memory cannot be allocated or `align` is not a power of 2.
+    pub fn try_new_uninit_slice_aligned(
+        len: usize,
+        align: usize,
+    ) -> Result<MBox<[MaybeUninit<T>]>, AllocError> {
+        let storage = try_gen_malloc_aligned(len, align)?;
+        // SAFETY: The storage is allowed to be uninitialized.
+        unsafe { Ok(MBox::from_raw_parts(storage.as_ptr(), len)) }
     }
-}
 
-impl<T: Clone> Clone for MBox<[T]> {
-    fn clone(&self) -> Self {
-        Self::from_slice(self)
+    /// Constructs a new boxed slice with the contents filled with zeros, aligned to at least
+    /// `align` bytes.
+    ///
+    /// Like `new_uninit_slice_aligned()`, the alignment is lost if the slice is grown afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of 2.
+    pub fn new_zeroed_slice_aligned(len: usize, align: usize) -> MBox<[MaybeUninit<T>]> {
+        Self::try_new_zeroed_slice_aligned(len, align).unwrap_or_else(|e| e.handle())
     }
-}
 
-impl<T: Clone> MBox<[T]> {
-    /// Creates a new `malloc`-boxed slice by cloning the content of an existing slice.
-    pub fn from_slice(slice: &[T]) -> MBox<[T]> {
-        let mut builder = MSliceBuilder::with_capacity(slice.len());
-        for item in slice {
-            builder.push(item.clone());
+    /// Constructs a new boxed slice with the contents filled with zeros, aligned to at least
+    /// `align` bytes. Returns an `AllocError` if the memory cannot be allocated or `align` is not a
+    /// power of 2.
+    pub fn try_new_zeroed_slice_aligned(
+        len: usize,
+        align: usize,
+    ) -> Result<MBox<[MaybeUninit<T>]>, AllocError> {
+        let storage = try_gen_calloc_aligned(len, align)?;
+        // SAFETY: The storage is allowed to be uninitialized, let alone zeroed.
+        unsafe { Ok(MBox::from_raw_parts(storage.as_ptr(), len)) }
+    }
+
+    /// Constructs a new boxed slice with uninitialized contents, backed by transparent huge pages
+    /// when the buffer is large enough.
+    ///
+    /// Buffers spanning at least one huge page are aligned to the huge page size, and the kernel is
+    /// advised with `madvise(MADV_HUGEPAGE)` to back them with huge pages. The memory can still be
+    /// released with plain `free()`.
+    ///
+    /// Like `new_uninit_slice_aligned()`, the alignment is lost if the slice is grown afterwards.
+    #[cfg(target_os = "linux")]
+    pub fn new_uninit_slice_huge(len: usize) -> MBox<[MaybeUninit<T>]> {
+        Self::try_new_uninit_slice_huge(len).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Constructs a new boxed slice with uninitialized contents, backed by transparent huge pages
+    /// when the buffer is large enough. Returns an `AllocError` if the memory cannot be allocated.
+    #[cfg(target_os = "linux")]
+    pub fn try_new_uninit_slice_huge(len: usize) -> Result<MBox<[MaybeUninit<T>]>, AllocError> {
+        let size = len.saturating_mul(size_of::<T>());
+        if size < HUGE_PAGE_SIZE {
+            return Self::try_new_uninit_slice(len);
+        }
+        let storage = try_gen_malloc_aligned::<MaybeUninit<T>>(len, HUGE_PAGE_SIZE)?;
+        // SAFETY: The storage is aligned to `HUGE_PAGE_SIZE` and spans `size` bytes. It is allowed
+        // to be uninitialized.
+        unsafe {
+            advise_huge_pages(storage.as_ptr() as *mut c_void, size);
+            Ok(MBox::from_raw_parts(storage.as_ptr(), len))
         }
-        builder.into_mboxed_slice()
     }
-}
 
-impl<T> FromIterator<T> for MBox<[T]> {
-    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+    /// Creates a new `malloc`-boxed slice from an iterator. Returns an `AllocError` if the memory
+    /// cannot be allocated.
+    ///
+    /// If the iterator reports an exact size, the memory is allocated once. Otherwise, the buffer
+    /// is grown as needed and then shrunk with `realloc()` to fit the collected items.
+    pub fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, AllocError> {
         let iter = iter.into_iter();
         let (lower_size, upper_size) = iter.size_hint();
-        let initial_capacity = upper_size.unwrap_or(lower_size).max(1);
-        let mut builder = MSliceBuilder::with_capacity(initial_capacity);
+        let initial_capacity = if upper_size == Some(lower_size) {
+            lower_size
+        } else {
+            lower_size.max(1)
+        };
+        let mut vec = MVec::try_with_capacity(initial_capacity)?;
         for item in iter {
-            builder.push(item);
+            vec.try_push(item)?;
+        }
+        if (vec.capacity() - vec.len()).saturating_mul(size_of::<T>()) >= SHRINK_THRESHOLD {
+            vec.try_shrink_to_fit()?;
         }
-        builder.into_mboxed_slice()
+        Ok(vec.into_boxed_slice())
     }
-}
 
-impl<T> IntoIterator for MBox<[T]> {
-    type Item = T;
-    type IntoIter = MSliceIntoIter<T>;
-    fn into_iter(self) -> MSliceIntoIter<T> {
-        let (ptr, len) = self.into_raw_parts();
-        MSliceIntoIter {
-            ptr: unsafe { NonNull::new_unchecked(ptr) },
-            begin: 0,
-            end: len,
+    /// Replaces the pointer and length of the boxed slice, without dropping or freeing the
+    /// original content.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be the current pointer of the box, or the pointer it was reallocated to, and
+    /// the first `len` items must be initialized. The allocation stays owned by the same box, so
+    /// no allocation hook is involved.
+    unsafe fn set_raw_parts(&mut self, ptr: *mut T, len: usize) {
+        self.0 = Unique::new(NonNull::new_unchecked(slice_from_raw_parts_mut(ptr, len)));
+    }
+
+    /// Reallocates the boxed slice to hold `additional` more items, and fills them by calling
+    /// `f(i)` for `i` in `0..additional`.
+    fn extend_with<F: FnMut(usize) -> T>(&mut self, additional: usize, mut f: F) {
+        if additional == 0 {
+            return;
+        }
+        let len = self.len();
+        let new_len = len.checked_add(additional).expect("memory overflow");
+        // SAFETY: the pointer is obtained from `malloc()`. The length is increased one at a time
+        // after every item is initialized, so the box remains valid even if `f` panics. This only
+        // rewrites the fat pointer and does not touch the allocation hooks.
+        unsafe {
+            let old_ptr = NonNull::new_unchecked(Self::as_mut_ptr(self) as *mut T);
+            let ptr = if new_len <= usable_count(old_ptr, len) {
+                old_ptr.as_ptr()
+            } else {
+                gen_realloc(old_ptr, len, new_len).as_ptr()
+            };
+            self.set_raw_parts(ptr, len);
+            for i in 0..additional {
+                write(ptr.add(len + i), f(i));
+                self.set_raw_parts(ptr, len + i + 1);
+            }
         }
     }
-}
 
-impl<'a, T> IntoIterator for &'a MBox<[T]> {
-    type Item = &'a T;
-    type IntoIter = Iter<'a, T>;
-    fn into_iter(self) -> Iter<'a, T> {
-        self.iter()
+    /// Appends an item to the end of the boxed slice.
+    ///
+    /// The buffer is reallocated using `realloc()` unless the allocation still has enough usable
+    /// space, so the pointer remains compatible with `free()`.
+    pub fn push(&mut self, value: T) {
+        let mut value = Some(value);
+        self.extend_with(1, |_| value.take().unwrap());
     }
-}
 
-impl<'a, T> IntoIterator for &'a mut MBox<[T]> {
-    type Item = &'a mut T;
-    type IntoIter = IterMut<'a, T>;
+    /// Resizes the boxed slice in place, so that the length is equal to `new_len`.
+    ///
+    /// If `new_len` is greater than the current length, the slice is extended by the values
+    /// returned from calling the closure `f`. If `new_len` is less than the current length, the
+    /// slice is truncated.
+    pub fn resize_with<F: FnMut() -> T>(&mut self, new_len: usize, mut f: F) {
+        let len = self.len();
+        if new_len > len {
+            self.extend_with(new_len - len, |_| f());
+        } else {
+            self.truncate(new_len);
+        }
+    }
+
+    /// Shortens the boxed slice, keeping the first `len` items and dropping the rest. The
+    /// allocation is shrunk using `realloc()`.
+    ///
+    /// If `len` is greater than or equal to the current length, this has no effect.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.drain(len..);
+        }
+    }
+
+    /// Removes the specified range from the boxed slice, returning all removed items as an
+    /// iterator. The allocation is shrunk using `realloc()` when the iterator is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the starting point is greater than the end point, or if the end point is greater
+    /// than the length of the slice.
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> MSliceDrain<'_, T> {
+        let len = self.len();
+        let (start, end) = slice_range(range, len);
+        // SAFETY: we temporarily shorten the slice to `start`, so that the drained items and the
+        // tail will not be dropped twice even if the `MSliceDrain` is leaked.
+        unsafe {
+            let ptr = Self::as_mut_ptr(self) as *mut T;
+            self.set_raw_parts(ptr, start);
+        }
+        MSliceDrain {
+            slice: self,
+            begin: start,
+            end,
+            tail_start: end,
+            tail_len: len - end,
+        }
+    }
+}
+
+impl<T, D: Dealloc> MBox<[MaybeUninit<T>], D> {
+    /// Converts into an initialized boxed slice.
+    ///
+    /// # Safety
+    ///
+    /// The caller should guarantee `*self` is indeed initialized.
+    pub unsafe fn assume_init(self) -> MBox<[T], D> {
+        let (ptr, len, dealloc) = self.into_raw_parts_with_dealloc();
+        MBox::from_raw_parts_in(ptr as *mut T, len, dealloc)
+    }
+}
+
+impl<T> Default for MBox<[T]> {
+    fn default() -> Self {
+        unsafe { Self::from_raw_parts(gen_malloc(0).as_ptr(), 0) }
+    }
+}
+
+impl<T: Clone> Clone for MBox<[T]> {
+    /// Clones the slice using `from_slice()`. Call `MBox::from_copy_slice()` instead to clone a
+    /// slice of `BitCopy` items with a single `memcpy()` on stable.
+    fn clone(&self) -> Self {
+        Self::from_slice(self)
+    }
+}
+
+impl<T: Clone> MBox<[T]> {
+    /// Creates a new `malloc`-boxed slice by cloning the content of an existing slice.
+    ///
+    /// With the `nightly` feature, this is a single `memcpy()` when `T` is a primitive type or a
+    /// raw pointer. Use `from_copy_slice()` to get the same on stable.
+    pub fn from_slice(slice: &[T]) -> MBox<[T]> {
+        Self::try_from_slice(slice).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Creates a new `malloc`-boxed slice by cloning the content of an existing slice. Returns an
+    /// `AllocError` if the memory cannot be allocated.
+    pub fn try_from_slice(slice: &[T]) -> Result<MBox<[T]>, AllocError> {
+        let mut vec = MVec::try_with_capacity(slice.len())?;
+        vec.extend_from_slice(slice);
+        Ok(vec.into_boxed_slice())
+    }
+
+    /// Resizes the boxed slice in place, so that the length is equal to `new_len`.
+    ///
+    /// If `new_len` is greater than the current length, the slice is extended by clones of
+    /// `value`. If `new_len` is less than the current length, the slice is truncated.
+    pub fn resize(&mut self, new_len: usize, value: T) {
+        self.resize_with(new_len, || value.clone());
+    }
+
+    /// Appends clones of all items in `other` to the end of the boxed slice, reallocating the
+    /// buffer using `realloc()`.
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        self.extend_with(other.len(), |i| other[i].clone());
+    }
+}
+
+impl<T: BitCopy> MBox<[T]> {
+    /// Creates a new `malloc`-boxed slice by copying the content of an existing slice using a
+    /// single `memcpy()`.
+    pub fn from_copy_slice(slice: &[T]) -> MBox<[T]> {
+        Self::try_from_copy_slice(slice).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Creates a new `malloc`-boxed slice by copying the content of an existing slice using a
+    /// single `memcpy()`. Returns an `AllocError` if the memory cannot be allocated.
+    pub fn try_from_copy_slice(slice: &[T]) -> Result<MBox<[T]>, AllocError> {
+        let mut vec = MVec::try_with_capacity(slice.len())?;
+        vec.extend_from_copy_slice(slice);
+        Ok(vec.into_boxed_slice())
+    }
+}
+
+impl<T> FromIterator<T> for MBox<[T]> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::try_from_iter(iter).unwrap_or_else(|e| e.handle())
+    }
+}
+
+impl<T, D: Dealloc> IntoIterator for MBox<[T], D> {
+    type Item = T;
+    type IntoIter = MSliceIntoIter<T, D>;
+    fn into_iter(self) -> MSliceIntoIter<T, D> {
+        // the iterator frees the pointer with the same deallocator, so it is not released here.
+        let (fat_ptr, dealloc) = MBox::into_owned_raw(self);
+        let (ptr, len) = slice_into_raw_parts_mut(fat_ptr.as_ptr());
+        MSliceIntoIter {
+            ptr: unsafe { NonNull::new_unchecked(ptr) },
+            begin: 0,
+            end: len,
+            dealloc,
+        }
+    }
+}
+
+impl<'a, T, D: Dealloc> IntoIterator for &'a MBox<[T], D> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T, D: Dealloc> IntoIterator for &'a mut MBox<[T], D> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
     fn into_iter(self) -> IterMut<'a, T> {
         self.iter_mut()
     }
@@ -892,6 +1498,28 @@ fn test_from_iterator_with_no_size_hint() {
     assert_eq!(&*slice, b"1234567890");
 }
 
+#[cfg(all(target_os = "linux", not(miri)))]
+#[test]
+fn test_from_iterator_shrinks_to_fit() {
+    const RECORD_SIZE: usize = 256;
+
+    // without an upper bound, the buffer grows exponentially to 2048 records before shrinking.
+    let slice = (0..)
+        .take_while(|&i| i < 1025)
+        .map(|i| [i as u8; RECORD_SIZE])
+        .collect::<MBox<[_]>>();
+    assert_eq!(slice.len(), 1025);
+    assert!(slice.iter().enumerate().all(|(i, r)| r[0] == i as u8));
+    let usable_size = MBox::usable_size(&slice);
+    assert!(usable_size >= 1025 * RECORD_SIZE);
+    assert!(usable_size <= 1025 * RECORD_SIZE + 4096);
+
+    // with an exact size hint, the buffer is allocated once with the exact size.
+    let slice = (0..1000u32).collect::<MBox<[_]>>();
+    assert_eq!(slice.len(), 1000);
+    assert!(MBox::usable_size(&slice) < 1000 * 4 + 64);
+}
+
 #[cfg(not(windows))]
 #[test]
 fn test_into_iterator() {
@@ -923,6 +1551,7 @@ fn test_iter_properties() {
 
 #[cfg(not(windows))]
 #[test]
+#[allow(clippy::unnecessary_operation)]
 fn test_iter_drop() {
     let counter = DropCounter::default();
     {
@@ -953,6 +1582,86 @@ fn test_zst_slice() {
     slice.into_iter();
 }
 
+#[cfg(not(windows))]
+#[test]
+fn test_slice_resize() {
+    let counter = DropCounter::default();
+    {
+        let mut slice = MBox::<[DropCounter]>::default();
+        slice.resize(5, counter.clone());
+        counter.assert_eq(1);
+        assert_eq!(slice.len(), 5);
+
+        slice.truncate(2);
+        counter.assert_eq(4);
+        assert_eq!(slice.len(), 2);
+
+        slice.truncate(10);
+        assert_eq!(slice.len(), 2);
+
+        slice.resize_with(3, DropCounter::default);
+        assert_eq!(slice.len(), 3);
+        slice[2].assert_eq(0);
+
+        slice.resize(0, counter.clone());
+        counter.assert_eq(7);
+        assert_eq!(slice.len(), 0);
+    }
+    counter.assert_eq(7);
+}
+
+#[test]
+fn test_slice_extend() {
+    let mut slice = MBox::from_slice(b"abc");
+    slice.push(b'd');
+    slice.extend_from_slice(b"efg");
+    slice.extend_from_slice(b"");
+    assert_eq!(&*slice, b"abcdefg");
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_slice_drain() {
+    let counter = DropCounter::default();
+    {
+        let mut slice = repeat(counter.clone()).take(8).collect::<MBox<[_]>>();
+        counter.assert_eq(1);
+        {
+            let mut drain = slice.drain(2..6);
+            assert_eq!(drain.len(), 4);
+            drain.next().unwrap().assert_eq(1);
+            drain.next_back().unwrap().assert_eq(2);
+            assert_eq!(drain.as_slice().len(), 2);
+        }
+        counter.assert_eq(5);
+        assert_eq!(slice.len(), 4);
+    }
+    counter.assert_eq(9);
+
+    let mut slice = MBox::from_slice(&[1u64, 2, 3, 4, 5, 6]);
+    assert!(slice.drain(1..=2).eq([2, 3].iter().copied()));
+    assert_eq!(&*slice, &[1, 4, 5, 6]);
+    assert!(slice.drain(..).rev().eq([6, 5, 4, 1].iter().copied()));
+    assert_eq!(slice.len(), 0);
+}
+
+#[test]
+#[should_panic(expected = "out of range")]
+fn test_slice_drain_out_of_bounds() {
+    let mut slice = MBox::from_slice(b"abc");
+    slice.drain(1..4);
+}
+
+#[test]
+fn test_zst_slice_resize() {
+    let mut slice = MBox::<[()]>::default();
+    slice.resize(10, ());
+    slice.push(());
+    assert_eq!(slice.len(), 11);
+    assert_eq!(slice.drain(3..).count(), 8);
+    assert_eq!(slice.len(), 3);
+}
+
 #[test]
 #[should_panic(expected = "panic on clone")]
 fn test_panic_during_clone() {
@@ -972,6 +1681,99 @@ fn test_panic_during_clone_from() {
 
 //{{{ UTF-8 String --------------------------------------------------------------------------------
 
+/// An error indicating that the bytes cannot be converted into a string because they are not valid
+/// UTF-8 (similar to `std::string::FromUtf8Error`).
+///
+/// The original bytes can be recovered using `into_bytes()`.
+pub struct FromUtf8Error<D: Dealloc = LibcFree> {
+    bytes: MBox<[u8], D>,
+    error: Utf8Error,
+}
+
+impl<D: Dealloc> FromUtf8Error<D> {
+    /// Returns the bytes which failed the conversion.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the bytes which failed the conversion, giving back the ownership of the buffer.
+    pub fn into_bytes(self) -> MBox<[u8], D> {
+        self.bytes
+    }
+
+    /// Returns the UTF-8 error which caused the conversion to fail.
+    pub fn utf8_error(&self) -> Utf8Error {
+        self.error
+    }
+}
+
+impl Clone for FromUtf8Error {
+    fn clone(&self) -> Self {
+        FromUtf8Error {
+            bytes: self.bytes.clone(),
+            error: self.error,
+        }
+    }
+}
+
+impl<D: Dealloc> PartialEq for FromUtf8Error<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes && self.error == other.error
+    }
+}
+
+impl<D: Dealloc> Eq for FromUtf8Error<D> {}
+
+impl<D: Dealloc> Debug for FromUtf8Error<D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter
+            .debug_struct("FromUtf8Error")
+            .field("bytes", &self.bytes)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<D: Dealloc> Display for FromUtf8Error<D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Display::fmt(&self.error, formatter)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: Dealloc> std::error::Error for FromUtf8Error<D> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<D: Dealloc> MBox<str, D> {
+    /// Converts the string into raw bytes.
+    pub fn into_bytes(self) -> MBox<[u8], D> {
+        let (ptr, dealloc) = Self::into_non_null_raw_with_dealloc(self);
+        unsafe { MBox::from_raw_in(ptr.as_ptr() as *mut [u8], dealloc) }
+    }
+
+    /// Creates a string from raw bytes.
+    ///
+    /// # Safety
+    ///
+    /// The raw bytes must be valid UTF-8.
+    pub unsafe fn from_utf8_unchecked(bytes: MBox<[u8], D>) -> MBox<str, D> {
+        let (ptr, dealloc) = MBox::into_non_null_raw_with_dealloc(bytes);
+        Self::from_raw_in(ptr.as_ptr() as *mut str, dealloc)
+    }
+
+    /// Creates a string from raw bytes. If the content does not contain valid UTF-8, this method
+    /// returns an `Err`, from which the original bytes can be recovered.
+    pub fn from_utf8(bytes: MBox<[u8], D>) -> Result<MBox<str, D>, FromUtf8Error<D>> {
+        match from_utf8(&bytes) {
+            Ok(_) => unsafe { Ok(Self::from_utf8_unchecked(bytes)) },
+            Err(error) => Err(FromUtf8Error { bytes, error }),
+        }
+    }
+}
+
 impl MBox<str> {
     /// Constructs a new malloc-backed string from the pointer and the length (number of UTF-8 code
     /// units).
@@ -985,35 +1787,143 @@ impl MBox<str> {
     }
 
     /// Constructs a new malloc-backed string from the pointer and the length (number of UTF-8 code
-    /// units). If the content does not contain valid UTF-8, this method returns an `Err`.
+    /// units). If the content does not contain valid UTF-8, this method returns an `Err`, which
+    /// still owns the buffer.
     ///
     /// # Safety
     ///
     /// The `malloc`ed size of the pointer must be at least `len`.
     /// The content must already been initialized.
-    pub unsafe fn from_raw_utf8_parts(value: *mut u8, len: usize) -> Result<MBox<str>, Utf8Error> {
+    pub unsafe fn from_raw_utf8_parts(
+        value: *mut u8,
+        len: usize,
+    ) -> Result<MBox<str>, FromUtf8Error> {
         Self::from_utf8(MBox::from_raw_parts(value, len))
     }
 
-    /// Converts the string into raw bytes.
-    pub fn into_bytes(self) -> MBox<[u8]> {
-        unsafe { MBox::from_raw(Self::into_raw(self) as *mut [u8]) }
+    /// Creates a string from raw bytes, replacing any invalid UTF-8 sequences with U+FFFD
+    /// REPLACEMENT CHARACTER.
+    ///
+    /// If the bytes are already valid UTF-8, the buffer is reused without copying. Otherwise, a new
+    /// `malloc`ed buffer is allocated and the original one is freed.
+    pub fn from_utf8_lossy(bytes: MBox<[u8]>) -> MBox<str> {
+        const REPLACEMENT: &[u8] = b"\xef\xbf\xbd";
+
+        let mut error = match from_utf8(&bytes) {
+            Ok(_) => return unsafe { Self::from_utf8_unchecked(bytes) },
+            Err(e) => e,
+        };
+        let mut vec = MVec::with_capacity(bytes.len());
+        let mut rest: &[u8] = &bytes;
+        loop {
+            let valid_up_to = error.valid_up_to();
+            vec.extend_from_slice(&rest[..valid_up_to]);
+            vec.extend_from_slice(REPLACEMENT);
+            match error.error_len() {
+                Some(len) => rest = &rest[(valid_up_to + len)..],
+                None => break,
+            }
+            match from_utf8(rest) {
+                Ok(_) => {
+                    vec.extend_from_slice(rest);
+                    break;
+                }
+                Err(e) => error = e,
+            }
+        }
+        // SAFETY: the content consists of valid UTF-8 sequences and replacement characters only.
+        unsafe { Self::from_utf8_unchecked(vec.into_boxed_slice()) }
     }
 
-    /// Creates a string from raw bytes.
+    /// Replaces the pointer and length of the boxed string, without freeing the original content.
     ///
     /// # Safety
     ///
-    /// The raw bytes must be valid UTF-8.
-    pub unsafe fn from_utf8_unchecked(bytes: MBox<[u8]>) -> MBox<str> {
-        Self::from_raw(MBox::into_raw(bytes) as *mut str)
+    /// The `value` must be the current pointer of the box, or the pointer it was reallocated to,
+    /// and the first `len` bytes must be valid UTF-8.
+    unsafe fn set_raw_parts(&mut self, value: *mut u8, len: usize) {
+        let ptr = slice_from_raw_parts_mut(value, len) as *mut str;
+        self.0 = Unique::new(NonNull::new_unchecked(ptr));
+    }
+
+    /// Appends a string slice to the end of the boxed string, reallocating the buffer using
+    /// `realloc()` unless the allocation still has enough usable space.
+    pub fn push_str(&mut self, string: &str) {
+        if string.is_empty() {
+            return;
+        }
+        let len = self.len();
+        let new_len = len.checked_add(string.len()).expect("memory overflow");
+        // SAFETY: the pointer is obtained from `malloc()`. The content remains valid UTF-8 since
+        // we are concatenating two UTF-8 strings.
+        unsafe {
+            let old_ptr = NonNull::new_unchecked(Self::as_mut_ptr(self) as *mut u8);
+            let ptr = if new_len <= usable_count(old_ptr, len) {
+                old_ptr.as_ptr()
+            } else {
+                gen_realloc(old_ptr, len, new_len).as_ptr()
+            };
+            copy_nonoverlapping(string.as_ptr(), ptr.add(len), string.len());
+            self.set_raw_parts(ptr, new_len);
+        }
     }
 
-    /// Creates a string from raw bytes. If the content does not contain valid UTF-8, this method
-    /// returns an `Err`.
-    pub fn from_utf8(bytes: MBox<[u8]>) -> Result<MBox<str>, Utf8Error> {
-        from_utf8(&bytes)?;
-        unsafe { Ok(Self::from_utf8_unchecked(bytes)) }
+    /// Appends a character to the end of the boxed string, reallocating the buffer using
+    /// `realloc()`.
+    pub fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Shortens the boxed string to `new_len` bytes. The allocation is shrunk using `realloc()`.
+    ///
+    /// If `new_len` is greater than or equal to the current length, this has no effect.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` does not lie on a `char` boundary.
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len < self.len() {
+            self.drain(new_len..);
+        }
+    }
+
+    /// Removes the specified range from the boxed string, returning all removed characters as
+    /// an iterator. The allocation is shrunk using `realloc()` when the iterator is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the starting point or end point do not lie on a `char` boundary, or if they are
+    /// out of bounds.
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> MStrDrain<'_> {
+        let len = self.len();
+        let (start, end) = slice_range(range, len);
+        assert!(self.is_char_boundary(start));
+        assert!(self.is_char_boundary(end));
+        // SAFETY: we temporarily shorten the string to `start`, which is a char boundary.
+        unsafe {
+            let ptr = Self::as_mut_ptr(self) as *mut u8;
+            self.set_raw_parts(ptr, start);
+        }
+        MStrDrain {
+            string: self,
+            begin: start,
+            end,
+            tail_start: end,
+            tail_len: len - end,
+        }
+    }
+
+    /// Creates a new `malloc`-boxed string by cloning the content of an existing string slice.
+    /// Returns an `AllocError` if the memory cannot be allocated.
+    pub fn try_from_str(string: &str) -> Result<MBox<str>, AllocError> {
+        let len = string.len();
+        let new_slice = try_gen_malloc(len)?.as_ptr();
+        // SAFETY: `new_slice` is not null, allocated with size fitting `string`,
+        // and also `string` is guaranteed to be UTF-8.
+        unsafe {
+            copy_nonoverlapping(string.as_ptr(), new_slice, len);
+            Ok(Self::from_raw_utf8_parts_unchecked(new_slice, len))
+        }
     }
 }
 
@@ -1032,14 +1942,7 @@ impl Clone for MBox<str> {
 impl From<&str> for MBox<str> {
     /// Creates a new `malloc`-boxed string by cloning the content of an existing string slice.
     fn from(string: &str) -> Self {
-        let len = string.len();
-        let new_slice = gen_malloc(len).as_ptr();
-        // SAFETY: `new_slice` is not null, allocated with size fitting `string`,
-        // and also `string` is guaranteed to be UTF-8.
-        unsafe {
-            copy_nonoverlapping(string.as_ptr(), new_slice, len);
-            Self::from_raw_utf8_parts_unchecked(new_slice, len)
-        }
+        Self::try_from_str(string).unwrap_or_else(|e| e.handle())
     }
 }
 
@@ -1062,8 +1965,146 @@ fn test_string_with_internal_nul() {
 #[test]
 fn test_non_utf8() {
     let bytes = MBox::from_slice(b"\x88\x88\x88\x88");
-    let string = MBox::from_utf8(bytes);
-    assert!(string.is_err());
+    let ptr = bytes.as_ptr();
+    let err = MBox::from_utf8(bytes).unwrap_err();
+    assert_eq!(err.utf8_error().valid_up_to(), 0);
+    assert_eq!(err.as_bytes(), b"\x88\x88\x88\x88");
+    let bytes = err.into_bytes();
+    assert_eq!(bytes.as_ptr(), ptr);
+}
+
+#[test]
+fn test_try_from() {
+    let slice = MBox::try_from_slice(&[1u8, 2, 3]).unwrap();
+    assert_eq!(&*slice, &[1, 2, 3]);
+
+    let slice = MBox::<[u8]>::try_from_iter(b"456".iter().copied()).unwrap();
+    assert_eq!(&*slice, b"456");
+
+    let string = MBox::<str>::try_from_str("789").unwrap();
+    assert_eq!(&*string, "789");
+}
+
+#[test]
+fn test_new_zeroed_slice() {
+    let slice = unsafe { MBox::<[u8]>::new_zeroed_slice(100).assume_init() };
+    assert_eq!(&*slice, &[0u8; 100][..]);
+
+    let empty = MBox::<[u8]>::new_zeroed_slice(0);
+    assert_eq!(empty.len(), 0);
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+#[test]
+fn test_usable_size() {
+    let a = MBox::new(1u8);
+    assert!(MBox::usable_size(&a) >= 1);
+
+    let mut slice = MBox::<[u16]>::from_slice(&[1, 2, 3]);
+    let usable_len = slice.usable_len();
+    assert!(usable_len >= 3);
+    let ptr = slice.as_ptr();
+    while slice.len() < usable_len {
+        slice.push(0);
+    }
+    assert_eq!(slice.as_ptr(), ptr);
+
+    let mut string = MBox::<str>::from("a");
+    let ptr = string.as_ptr();
+    string.push('b');
+    assert_eq!(string.as_ptr(), ptr);
+    assert_eq!(&*string, "ab");
+
+    assert_eq!(MBox::<[()]>::default().usable_len(), !0);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_new_slice_aligned() {
+    let slice = MBox::<[u8]>::new_uninit_slice_aligned(1000, 4096);
+    assert_eq!(slice.as_ptr() as usize % 4096, 0);
+    assert_eq!(slice.len(), 1000);
+
+    let slice = unsafe { MBox::<[u32]>::new_zeroed_slice_aligned(1000, 64).assume_init() };
+    assert_eq!(slice.as_ptr() as usize % 64, 0);
+    assert!(slice.iter().all(|x| *x == 0));
+
+    // the alignment of `T` is respected even if a smaller alignment is requested.
+    let slice = MBox::<[u64]>::new_uninit_slice_aligned(3, 1);
+    assert_eq!(slice.as_ptr() as usize % align_of::<u64>(), 0);
+
+    let empty = MBox::<[u8]>::new_zeroed_slice_aligned(0, 512);
+    assert_eq!(empty.as_ptr() as usize % 512, 0);
+    assert_eq!(empty.len(), 0);
+
+    let err = MBox::<[u16]>::try_new_uninit_slice_aligned(!0, 16)
+        .err()
+        .unwrap();
+    assert!(err.is_capacity_overflow());
+
+    let err = MBox::<[u8]>::try_new_zeroed_slice_aligned(16, 48)
+        .err()
+        .unwrap();
+    assert!(err.is_invalid_alignment());
+    assert!(err.layout().is_none());
+}
+
+#[test]
+#[should_panic(expected = "alignment must be a power of 2")]
+fn test_new_slice_aligned_invalid() {
+    MBox::<[u8]>::new_uninit_slice_aligned(16, 48);
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+#[test]
+fn test_new_slice_huge() {
+    let mut slice = MBox::<[u8]>::new_uninit_slice_huge(HUGE_PAGE_SIZE * 2);
+    assert_eq!(slice.as_ptr() as usize % HUGE_PAGE_SIZE, 0);
+    for b in slice.iter_mut() {
+        *b = MaybeUninit::new(1);
+    }
+    let slice = unsafe { slice.assume_init() };
+    assert_eq!(
+        slice.iter().map(|b| *b as usize).sum::<usize>(),
+        HUGE_PAGE_SIZE * 2
+    );
+
+    let small = MBox::<[u8]>::new_uninit_slice_huge(100);
+    assert_eq!(small.len(), 100);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_try_alloc_overflow() {
+    let err = MBox::<[u16]>::try_new_uninit_slice(!0).err().unwrap();
+    assert!(err.is_capacity_overflow());
+    assert_eq!(err.layout(), None);
+
+    let err = MBox::<[u16]>::try_new_zeroed_slice(!0).err().unwrap();
+    assert!(err.is_capacity_overflow());
+}
+
+#[test]
+fn test_str_mutation() {
+    let mut string = MBox::<str>::from("abc");
+    string.push('一');
+    string.push_str("def");
+    assert_eq!(&*string, "abc一def");
+
+    assert!(string.drain(1..6).eq("bc一".chars()));
+    assert_eq!(&*string, "adef");
+
+    string.truncate(2);
+    assert_eq!(&*string, "ad");
+    string.truncate(0);
+    assert_eq!(&*string, "");
+}
+
+#[test]
+#[should_panic(expected = "assertion failed: self.is_char_boundary(start)")]
+fn test_str_truncate_not_char_boundary() {
+    let mut string = MBox::<str>::from("一");
+    string.truncate(1);
 }
 
 #[test]
@@ -1078,4 +2119,603 @@ fn test_panic_on_clone_slice() {
     let _ = mbox.clone();
 }
 
+#[test]
+fn test_str_from_utf8_lossy() {
+    let bytes = MBox::from_slice(b"abc");
+    let ptr = bytes.as_ptr();
+    let string = MBox::<str>::from_utf8_lossy(bytes);
+    assert_eq!(&*string, "abc");
+    assert_eq!(string.as_ptr(), ptr);
+
+    let string = MBox::<str>::from_utf8_lossy(MBox::from_slice(b"a\xffb\xe2\x82"));
+    assert_eq!(&*string, "a\u{fffd}b\u{fffd}");
+
+    let string = MBox::<str>::from_utf8_lossy(MBox::from_slice(b"\xf0\x9f\x92\x96\xc0\xc1z\0"));
+    assert_eq!(&*string, "\u{1f496}\u{fffd}\u{fffd}z\0");
+}
+
+//}}}
+
+//{{{ Custom deallocator --------------------------------------------------------------------------
+
+#[cfg(not(windows))]
+#[test]
+fn test_custom_dealloc() {
+    let dealloc = DeallocCounter::default();
+    let drop_counter = DropCounter::default();
+    unsafe {
+        let ptr = gen_malloc::<DropCounter>(1).as_ptr();
+        write(ptr, drop_counter.clone());
+        let mbox = MBox::from_raw_in(ptr, dealloc.clone());
+        dealloc.assert_eq(0);
+        drop(mbox);
+        dealloc.assert_eq(1);
+        drop_counter.assert_eq(1);
+
+        let ptr = gen_malloc::<u64>(1).as_ptr();
+        write(ptr, 5);
+        let mbox = MBox::from_raw_in(ptr, dealloc.clone());
+        assert_eq!(MBox::into_inner(mbox), 5);
+        dealloc.assert_eq(2);
+
+        let ptr = gen_malloc::<DropCounter>(3).as_ptr();
+        for i in 0..3 {
+            write(ptr.add(i), drop_counter.clone());
+        }
+        let slice = MBox::from_raw_parts_in(ptr, 3, dealloc.clone());
+        let mut iter = slice.into_iter();
+        iter.next();
+        drop(iter);
+        dealloc.assert_eq(3);
+        drop_counter.assert_eq(4);
+
+        let ptr = gen_malloc::<u8>(2).as_ptr();
+        write(ptr, 0xff);
+        write(ptr.add(1), b'a');
+        let bytes = MBox::from_raw_parts_in(ptr, 2, dealloc.clone());
+        let err = MBox::from_utf8(bytes).unwrap_err();
+        dealloc.assert_eq(3);
+        let mut bytes = err.into_bytes();
+        bytes[0] = b'b';
+        let string = MBox::from_utf8(bytes).unwrap();
+        assert_eq!(&*string, "ba");
+        drop(string);
+        dealloc.assert_eq(4);
+    }
+}
+
+#[test]
+fn test_fn_free() {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn counting_free(ptr: *mut libc::c_void) {
+        FREED.fetch_add(1, SeqCst);
+        libc::free(ptr);
+    }
+
+    let dealloc = FnFree(counting_free);
+    unsafe {
+        let ptr = gen_malloc::<u8>(1).as_ptr();
+        write(ptr, 1);
+        let mbox = MBox::from_raw_in(ptr, dealloc);
+        assert_eq!(*mbox, 1);
+        drop(mbox);
+        assert_eq!(FREED.load(SeqCst), 1);
+
+        let ptr = gen_malloc::<u8>(3).as_ptr();
+        copy_nonoverlapping(b"xyz".as_ptr(), ptr, 3);
+        let string = MBox::from_utf8(MBox::from_raw_parts_in(ptr, 3, dealloc)).unwrap();
+        let bytes: MBox<[u8], FnFree> = string.into_bytes();
+        let reversed = bytes.into_iter().rev().collect::<MBox<[u8]>>();
+        assert_eq!(&*reversed, b"zyx");
+        assert_eq!(FREED.load(SeqCst), 2);
+    }
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_stateful_dealloc() {
+    let counter = DeallocCounter::default();
+    unsafe {
+        let a = MBox::from_raw_in(gen_malloc::<u8>(1).as_ptr(), &counter);
+        let b = MBox::from_raw_parts_in(gen_malloc::<u8>(0).as_ptr(), 0, &counter);
+        assert!(MBox::dealloc(&b).get() == 0);
+        drop(a);
+        counter.assert_eq(1);
+
+        let (ptr, len, dealloc) = b.into_raw_parts_with_dealloc();
+        assert_eq!(len, 0);
+        counter.assert_eq(1);
+        dealloc.dealloc(NonNull::new_unchecked(ptr).cast());
+        counter.assert_eq(2);
+    }
+}
+
+#[cfg(all(feature = "std", not(windows)))]
+#[test]
+fn test_shared_dealloc() {
+    use std::rc::Rc;
+
+    let counter = Rc::new(DeallocCounter::default());
+    unsafe {
+        let a = MBox::from_raw_in(gen_malloc::<u64>(1).as_ptr(), counter.clone());
+        let (ptr, dealloc) = MBox::into_raw_with_dealloc(a);
+        let b = MBox::from_raw_in(ptr, dealloc);
+        assert_eq!(Rc::strong_count(&counter), 2);
+        drop(b);
+    }
+    counter.assert_eq(1);
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
+
+//}}}
+
+//{{{ Handle --------------------------------------------------------------------------------------
+
+/// An owned handle to a C resource, which is destroyed by calling the destructor `F` on drop.
+///
+/// Unlike `MBox<T>`, the content is neither dropped nor freed; the destructor has full control of
+/// how the resource is released, e.g. `fclose()` or `sqlite3_finalize()`.
+///
+/// ```rust
+/// extern crate libc;
+/// extern crate mbox;
+///
+/// use libc::{c_char, fclose, fopen, FILE};
+/// use mbox::MHandle;
+///
+/// # #[cfg(unix)] {
+/// let file = unsafe {
+///     let ptr = fopen(b"/dev/null\0".as_ptr() as *const c_char, b"r\0".as_ptr() as *const c_char);
+///     MHandle::from_raw(ptr, fclose as unsafe extern "C" fn(*mut FILE) -> _)
+/// };
+/// assert!(file.is_some());
+/// // `fclose()` is called here.
+/// # }
+/// ```
+///
+/// `MHandle` does not implement `Send` or `Sync` unless the destructor implements
+/// `free::SendDestructor` or `free::SyncDestructor` respectively.
+pub struct MHandle<T, F: Destructor<T>> {
+    ptr: NonNull<T>,
+    destructor: F,
+}
+
+impl<T, F: Destructor<T>> MHandle<T, F> {
+    /// Takes ownership of a handle, which will be destroyed by `destructor`. Returns `None` if the
+    /// handle is null, in which case the destructor is not called.
+    ///
+    /// # Safety
+    ///
+    /// If not null, `ptr` must be a valid handle which is expected to be destroyed by
+    /// `destructor`. The handle's ownership is passed into the result, and thus should not be
+    /// destroyed elsewhere after this function returns.
+    pub unsafe fn from_raw(ptr: *mut T, destructor: F) -> Option<Self> {
+        NonNull::new(ptr).map(|ptr| Self::from_non_null_raw(ptr, destructor))
+    }
+
+    /// Takes ownership of a non-null handle, which will be destroyed by `destructor`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a valid handle which is expected to be destroyed by `destructor`. The handle's
+    /// ownership is passed into the result, and thus should not be destroyed elsewhere after this
+    /// function returns.
+    pub unsafe fn from_non_null_raw(ptr: NonNull<T>, destructor: F) -> Self {
+        Self { ptr, destructor }
+    }
+
+    /// Obtains the handle.
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Obtains the non-null handle.
+    pub fn as_non_null_ptr(&self) -> NonNull<T> {
+        self.ptr
+    }
+
+    /// Obtains a reference to the destructor.
+    pub fn destructor(&self) -> &F {
+        &self.destructor
+    }
+
+    /// Releases the ownership of the handle without destroying it. The destructor is dropped.
+    pub fn into_raw(self) -> *mut T {
+        self.into_raw_with_destructor().0
+    }
+
+    /// Releases the ownership of the handle without destroying it, returning the destructor as
+    /// well.
+    pub fn into_raw_with_destructor(self) -> (*mut T, F) {
+        let handle = ManuallyDrop::new(self);
+        // SAFETY: the handle is never used again after moving the destructor out.
+        unsafe { (handle.ptr.as_ptr(), read(&handle.destructor)) }
+    }
+}
+
+impl<T, F: Destructor<T>> Drop for MHandle<T, F> {
+    fn drop(&mut self) {
+        // SAFETY: the handle is assumed to be destroyed by `F`.
+        unsafe { self.destructor.destroy(self.ptr) };
+    }
+}
+
+unsafe impl<T, F: Destructor<T> + SendDestructor + Send> Send for MHandle<T, F> {}
+unsafe impl<T, F: Destructor<T> + SyncDestructor + Sync> Sync for MHandle<T, F> {}
+
+impl<T, F: Destructor<T>> Pointer for MHandle<T, F> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Pointer::fmt(&self.ptr, formatter)
+    }
+}
+
+impl<T, F: Destructor<T>> Debug for MHandle<T, F> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter.debug_tuple("MHandle").field(&self.ptr).finish()
+    }
+}
+
+#[test]
+fn test_handle() {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn destroy(ptr: *mut libc::c_void) -> libc::c_int {
+        DESTROYED.fetch_add(1, SeqCst);
+        libc::free(ptr);
+        0
+    }
+
+    type Handle = MHandle<libc::c_void, unsafe extern "C" fn(*mut libc::c_void) -> libc::c_int>;
+
+    assert_eq!(size_of::<Option<Handle>>(), size_of::<Handle>());
+
+    unsafe {
+        assert!(Handle::from_raw(null_mut(), destroy).is_none());
+
+        let handle = Handle::from_raw(libc::malloc(8), destroy).unwrap();
+        assert!(!handle.as_ptr().is_null());
+        drop(handle);
+        assert_eq!(DESTROYED.load(SeqCst), 1);
+
+        let handle = Handle::from_raw(libc::malloc(8), destroy).unwrap();
+        let (ptr, destructor) = handle.into_raw_with_destructor();
+        assert_eq!(DESTROYED.load(SeqCst), 1);
+        destructor.destroy(NonNull::new_unchecked(ptr));
+        assert_eq!(DESTROYED.load(SeqCst), 2);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_handle_send() {
+    let destructor = unsafe { AssertThreadSafe::new(libc::free as unsafe extern "C" fn(_)) };
+    let handle = unsafe { MHandle::from_raw(libc::malloc(8), destructor) }.unwrap();
+    std::thread::spawn(move || drop(handle)).join().unwrap();
+}
+
+//}}}
+
+//{{{ Global allocator ----------------------------------------------------------------------------
+
+#[cfg(feature = "global_malloc")]
+impl<T> MBox<T> {
+    /// Converts a `Box<T>` into an `MBox<T>` without copying.
+    ///
+    /// # Safety
+    ///
+    /// `mbox::Malloc` must be registered as the `#[global_allocator]`.
+    pub unsafe fn from_box(boxed: Box<T>) -> Self {
+        if size_of::<T>() == 0 {
+            // a zero-sized `Box` does not own any allocation, so we have to create one.
+            return Self::new(*boxed);
+        }
+        Self::from_raw(Box::into_raw(boxed))
+    }
+
+    /// Converts an `MBox<T>` into a `Box<T>` without copying.
+    ///
+    /// # Safety
+    ///
+    /// `mbox::Malloc` must be registered as the `#[global_allocator]`.
+    pub unsafe fn into_box(boxed: Self) -> Box<T> {
+        if size_of::<T>() == 0 {
+            return Box::new(Self::into_inner(boxed));
+        }
+        // the allocation is still alive, it is merely no longer owned by `mbox`.
+        let ptr = Self::into_raw(boxed);
+        Box::from_raw(ptr)
+    }
+}
+
+#[cfg(feature = "global_malloc")]
+impl<T> MBox<[T]> {
+    /// Converts a `Vec<T>` into an `MBox<[T]>` without copying. Any excess capacity of the vector
+    /// is kept in the allocation.
+    ///
+    /// # Safety
+    ///
+    /// `mbox::Malloc` must be registered as the `#[global_allocator]`.
+    pub unsafe fn from_vec(vec: Vec<T>) -> Self {
+        if size_of::<T>() == 0 || vec.capacity() == 0 {
+            // the vector does not own any allocation, so we have to create one.
+            return vec.into_iter().collect();
+        }
+        let mut vec = ManuallyDrop::new(vec);
+        Self::from_raw_parts(vec.as_mut_slice().as_mut_ptr(), vec.len())
+    }
+
+    /// Converts an `MBox<[T]>` into a `Vec<T>` without copying.
+    ///
+    /// # Safety
+    ///
+    /// `mbox::Malloc` must be registered as the `#[global_allocator]`.
+    pub unsafe fn into_vec(self) -> Vec<T> {
+        if size_of::<T>() == 0 || self.is_empty() {
+            // a vector with no capacity will never free the allocation, so release it here.
+            return self.into_iter().collect();
+        }
+        // the allocation is still alive, it is merely no longer owned by `mbox`.
+        let (ptr, len) = self.into_raw_parts();
+        Vec::from_raw_parts(ptr, len, len)
+    }
+}
+
+#[cfg(feature = "global_malloc")]
+impl MBox<str> {
+    /// Converts a `String` into an `MBox<str>` without copying.
+    ///
+    /// # Safety
+    ///
+    /// `mbox::Malloc` must be registered as the `#[global_allocator]`.
+    pub unsafe fn from_string(string: String) -> Self {
+        Self::from_utf8_unchecked(MBox::from_vec(string.into_bytes()))
+    }
+
+    /// Converts an `MBox<str>` into a `String` without copying.
+    ///
+    /// # Safety
+    ///
+    /// `mbox::Malloc` must be registered as the `#[global_allocator]`.
+    pub unsafe fn into_string(self) -> String {
+        String::from_utf8_unchecked(self.into_bytes().into_vec())
+    }
+}
+
+#[cfg(feature = "global_malloc")]
+#[test]
+fn test_box_conversion() {
+    unsafe {
+        let boxed = Box::new(42_u64);
+        let ptr = &*boxed as *const u64;
+        let mboxed = MBox::from_box(boxed);
+        assert_eq!(MBox::as_ptr(&mboxed), ptr);
+        assert_eq!(*mboxed, 42);
+
+        let boxed = MBox::into_box(mboxed);
+        assert_eq!(&*boxed as *const u64, ptr);
+        assert_eq!(*boxed, 42);
+
+        let unit = MBox::into_box(MBox::from_box(Box::new(())));
+        assert_eq!(*unit, ());
+    }
+}
+
+#[cfg(all(feature = "global_malloc", not(windows)))]
+#[test]
+fn test_vec_conversion() {
+    let counter = DropCounter::default();
+    unsafe {
+        let mut vec = Vec::with_capacity(10);
+        vec.resize(3, counter.clone());
+        let ptr = vec.as_ptr();
+        let mboxed = MBox::from_vec(vec);
+        assert_eq!(mboxed.as_ptr(), ptr);
+        assert_eq!(mboxed.len(), 3);
+        counter.assert_eq(0);
+
+        let mut vec = mboxed.into_vec();
+        assert_eq!(vec.as_ptr(), ptr);
+        assert_eq!(vec.len(), 3);
+        vec.push(counter.clone());
+        counter.assert_eq(0);
+        drop(vec);
+        counter.assert_eq(4);
+
+        let empty = MBox::<[u32]>::from_vec(Vec::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.into_vec(), Vec::<u32>::new());
+
+        let mut units = Vec::new();
+        units.resize(3, ());
+        let units = MBox::from_vec(units);
+        assert_eq!(units.len(), 3);
+        assert_eq!(units.into_vec().len(), 3);
+    }
+}
+
+#[cfg(feature = "global_malloc")]
+#[test]
+fn test_string_conversion() {
+    unsafe {
+        let string = String::from("hello");
+        let ptr = string.as_ptr();
+        let mboxed = MBox::from_string(string);
+        assert_eq!(mboxed.as_ptr(), ptr);
+        assert_eq!(&*mboxed, "hello");
+
+        let mut string = mboxed.into_string();
+        assert_eq!(string.as_ptr(), ptr);
+        string.push_str(", world");
+        assert_eq!(string, "hello, world");
+
+        assert_eq!(MBox::from_string(String::new()).into_string(), "");
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> From<Box<T, MallocAlloc>> for MBox<T> {
+    fn from(boxed: Box<T, MallocAlloc>) -> Self {
+        if size_of::<T>() == 0 {
+            // a zero-sized `Box` does not own any allocation, so we have to create one.
+            return Self::new(*boxed);
+        }
+        let (ptr, _) = Box::into_raw_with_allocator(boxed);
+        // SAFETY: the pointer is allocated by `MallocAlloc`, i.e. `malloc()`.
+        unsafe { Self::from_raw(ptr) }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> MBox<T> {
+    /// Converts an `MBox<T>` into a `Box<T, MallocAlloc>` without copying.
+    pub fn into_malloc_box(boxed: Self) -> Box<T, MallocAlloc> {
+        if size_of::<T>() == 0 {
+            return Box::new_in(Self::into_inner(boxed), MallocAlloc);
+        }
+        // the allocation is still alive, it is merely no longer owned by `mbox`.
+        let ptr = Self::into_raw(boxed);
+        // SAFETY: the pointer is allocated by `malloc()` and can be released by `MallocAlloc`.
+        unsafe { Box::from_raw_in(ptr, MallocAlloc) }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> From<Vec<T, MallocAlloc>> for MBox<[T]> {
+    /// Converts the vector without copying. Any excess capacity is kept in the allocation.
+    fn from(vec: Vec<T, MallocAlloc>) -> Self {
+        if size_of::<T>() == 0 || vec.capacity() == 0 {
+            // the vector does not own any allocation, so we have to create one.
+            return vec.into_iter().collect();
+        }
+        let mut vec = ManuallyDrop::new(vec);
+        // SAFETY: the pointer is allocated by `MallocAlloc`, i.e. `malloc()`.
+        unsafe { Self::from_raw_parts(vec.as_mut_slice().as_mut_ptr(), vec.len()) }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> From<MBox<[T]>> for Vec<T, MallocAlloc> {
+    fn from(slice: MBox<[T]>) -> Self {
+        if size_of::<T>() == 0 || slice.is_empty() {
+            // a vector with no capacity will never free the allocation, so release it here.
+            let mut vec = Vec::new_in(MallocAlloc);
+            vec.extend(slice);
+            return vec;
+        }
+        // the allocation is still alive, it is merely no longer owned by `mbox`.
+        let (ptr, len) = slice.into_raw_parts();
+        // SAFETY: the pointer is allocated by `malloc()` and can be released by `MallocAlloc`.
+        unsafe { Vec::from_raw_parts_in(ptr, len, len, MallocAlloc) }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> From<Box<[T], MallocAlloc>> for MBox<[T]> {
+    fn from(boxed: Box<[T], MallocAlloc>) -> Self {
+        Self::from(boxed.into_vec())
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> From<MBox<[T]>> for Box<[T], MallocAlloc> {
+    fn from(slice: MBox<[T]>) -> Self {
+        Vec::from(slice).into_boxed_slice()
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl From<Box<str, MallocAlloc>> for MBox<str> {
+    fn from(boxed: Box<str, MallocAlloc>) -> Self {
+        let (ptr, alloc) = Box::into_raw_with_allocator(boxed);
+        // SAFETY: `str` and `[u8]` have the same layout, and the content is valid UTF-8.
+        unsafe {
+            let bytes = Box::from_raw_in(ptr as *mut [u8], alloc);
+            Self::from_utf8_unchecked(MBox::from(bytes))
+        }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl From<MBox<str>> for Box<str, MallocAlloc> {
+    fn from(string: MBox<str>) -> Self {
+        let bytes = Box::<[u8], MallocAlloc>::from(string.into_bytes());
+        let (ptr, alloc) = Box::into_raw_with_allocator(bytes);
+        // SAFETY: `str` and `[u8]` have the same layout, and the content is valid UTF-8.
+        unsafe { Box::from_raw_in(ptr as *mut str, alloc) }
+    }
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+fn test_allocator_box_conversion() {
+    let boxed = Box::new_in(42_u64, MallocAlloc);
+    let ptr = &*boxed as *const u64;
+    let mboxed = MBox::<u64>::from(boxed);
+    assert_eq!(MBox::as_ptr(&mboxed), ptr);
+    assert_eq!(*mboxed, 42);
+
+    let boxed = MBox::into_malloc_box(mboxed);
+    assert_eq!(&*boxed as *const u64, ptr);
+    assert_eq!(*boxed, 42);
+
+    let unit = MBox::into_malloc_box(MBox::<()>::from(Box::new_in((), MallocAlloc)));
+    assert_eq!(*unit, ());
+}
+
+#[cfg(all(feature = "nightly", not(windows)))]
+#[test]
+fn test_allocator_vec_conversion() {
+    let counter = DropCounter::default();
+
+    let mut vec = Vec::with_capacity_in(10, MallocAlloc);
+    vec.resize(3, counter.clone());
+    let ptr = vec.as_ptr();
+    let mboxed = MBox::<[_]>::from(vec);
+    assert_eq!(mboxed.as_ptr(), ptr);
+    assert_eq!(mboxed.len(), 3);
+    counter.assert_eq(0);
+
+    let mut vec = Vec::<_, MallocAlloc>::from(mboxed);
+    assert_eq!(vec.as_ptr(), ptr);
+    assert_eq!(vec.len(), 3);
+    vec.push(counter.clone());
+    counter.assert_eq(0);
+    drop(vec);
+    counter.assert_eq(4);
+
+    let empty = MBox::<[u32]>::from(Vec::new_in(MallocAlloc));
+    assert!(empty.is_empty());
+    assert!(Vec::<u32, MallocAlloc>::from(empty).is_empty());
+
+    let mut units = Vec::new_in(MallocAlloc);
+    units.resize(3, ());
+    let units = MBox::<[()]>::from(units.into_boxed_slice());
+    assert_eq!(units.len(), 3);
+    assert_eq!(Box::<[()], MallocAlloc>::from(units).len(), 3);
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+fn test_allocator_str_conversion() {
+    let mut bytes = Vec::new_in(MallocAlloc);
+    bytes.extend_from_slice(b"hello");
+    let boxed = unsafe {
+        let (ptr, alloc) = Box::into_raw_with_allocator(bytes.into_boxed_slice());
+        Box::from_raw_in(ptr as *mut str, alloc)
+    };
+    let ptr = boxed.as_ptr();
+    let mboxed = MBox::<str>::from(boxed);
+    assert_eq!(mboxed.as_ptr(), ptr);
+    assert_eq!(&*mboxed, "hello");
+
+    let boxed = Box::<str, MallocAlloc>::from(mboxed);
+    assert_eq!(boxed.as_ptr(), ptr);
+    assert_eq!(&*boxed, "hello");
+}
+
 //}}}
@@ -0,0 +1,333 @@
+//! Debug registry of the allocations owned by `mbox`.
+//!
+//! When the `alloc_registry` feature is enabled, every allocation made by `mbox` (e.g. through
+//! `MBox::new()` or `MVec::push()`) and every pointer adopted from C (e.g. through
+//! `MBox::from_raw()` or `MString::from_raw_unchecked()`) is recorded, and removed again when
+//! `mbox` frees it. Pointers released with `MBox::into_raw()` or `MBox::leak()` stay in the
+//! registry until they are adopted and freed again, so they show up as leaks if C never returns
+//! them.
+//!
+//! ```rust
+//! extern crate mbox;
+//!
+//! use mbox::{registry, MBox};
+//!
+//! let ptr = MBox::into_raw(MBox::new(1_u32));
+//! assert!(registry::outstanding()
+//!     .iter()
+//!     .any(|a| a.address() == ptr as usize));
+//!
+//! drop(unsafe { MBox::from_raw(ptr) });
+//! assert!(registry::outstanding()
+//!     .iter()
+//!     .all(|a| a.address() != ptr as usize));
+//! ```
+//!
+//! The allocation site is captured using `std::backtrace::Backtrace::capture()`, which is only
+//! resolved when the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variable is set.
+//!
+//! The `check_pointers` feature additionally uses the registry to validate pointers. Adopting a
+//! null or misaligned pointer, or a pointer which is still owned by another box, panics
+//! immediately. So does freeing a pointer which `mbox` has never seen, instead of corrupting the
+//! heap inside `free()`.
+//!
+//! This module requires Rust 1.66.
+
+// the registry relies on `std::backtrace` (Rust 1.65) and `const BTreeMap::new()` (Rust 1.66),
+// which is the documented MSRV of the `alloc_registry` feature.
+#![allow(clippy::incompatible_msrv)]
+
+use libc::c_void;
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+/// How an allocation entered the registry.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Origin {
+    /// The memory was allocated by `mbox`.
+    Allocated,
+    /// The memory was allocated elsewhere and adopted by `mbox` through a `from_raw` function.
+    Adopted,
+}
+
+/// An outstanding allocation recorded by the registry.
+#[derive(Clone, Debug)]
+pub struct Allocation {
+    address: usize,
+    size: Option<usize>,
+    origin: Origin,
+    thread: ThreadId,
+    backtrace: Arc<Backtrace>,
+    owned: bool,
+}
+
+impl Allocation {
+    /// Returns the address of the allocation.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Returns the requested size of the allocation in bytes, or `None` if it was adopted.
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Returns how the allocation entered the registry.
+    pub fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    /// Returns the thread which made or adopted the allocation.
+    pub fn thread(&self) -> ThreadId {
+        self.thread
+    }
+
+    /// Returns the backtrace of the allocation site.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl Display for Allocation {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        write!(formatter, "{:#x} ", self.address)?;
+        match (self.origin, self.size) {
+            (Origin::Allocated, Some(size)) => write!(formatter, "({} bytes allocated", size)?,
+            _ => formatter.write_str("(adopted")?,
+        }
+        write!(formatter, " on {:?})", self.thread)?;
+        if self.backtrace.status() == BacktraceStatus::Captured {
+            write!(formatter, "\n{}", self.backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+static REGISTRY: Mutex<BTreeMap<usize, Allocation>> = Mutex::new(BTreeMap::new());
+
+/// Locks the registry. A poisoned lock is still usable since every update is a single map
+/// operation.
+fn registry() -> MutexGuard<'static, BTreeMap<usize, Allocation>> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn new_allocation(ptr: *const c_void, size: Option<usize>, origin: Origin) -> Allocation {
+    Allocation {
+        address: ptr as usize,
+        size,
+        origin,
+        thread: thread::current().id(),
+        backtrace: Arc::new(Backtrace::capture()),
+        owned: origin == Origin::Adopted,
+    }
+}
+
+/// Records a new allocation of `size` bytes made by `mbox`.
+pub(crate) fn record_alloc(ptr: *const c_void, size: usize) {
+    let allocation = new_allocation(ptr, Some(size), Origin::Allocated);
+    registry().insert(ptr as usize, allocation);
+}
+
+/// Records a pointer being adopted by a box. A pointer allocated by `mbox` keeps its original
+/// record.
+///
+/// With the `check_pointers` feature, panics if the pointer is already owned by another box.
+pub(crate) fn record_adopt(ptr: *const c_void) {
+    let mut registry = registry();
+    match registry.get_mut(&(ptr as usize)) {
+        None => {
+            let allocation = new_allocation(ptr, None, Origin::Adopted);
+            registry.insert(ptr as usize, allocation);
+        }
+        Some(allocation) if allocation.owned && cfg!(feature = "check_pointers") => {
+            let message = format!("mbox: {} is already owned by another box", allocation);
+            drop(registry);
+            panic!("{}", message);
+        }
+        Some(allocation) => allocation.owned = true,
+    }
+}
+
+/// Records a pointer being released from a box (e.g. by `MBox::into_raw()`), while the memory
+/// itself is still alive.
+pub(crate) fn record_release(ptr: *const c_void) {
+    if let Some(allocation) = registry().get_mut(&(ptr as usize)) {
+        allocation.owned = false;
+    }
+}
+
+/// Records a pointer being freed, or otherwise no longer owned by `mbox`.
+///
+/// With the `check_pointers` feature, panics if the pointer has never been seen by `mbox`.
+pub(crate) fn record_free(ptr: *const c_void) {
+    let known = registry().remove(&(ptr as usize)).is_some();
+    if !known && cfg!(feature = "check_pointers") {
+        panic!(
+            "mbox: cannot free {:p}, which was neither allocated nor adopted by mbox",
+            ptr
+        );
+    }
+}
+
+/// Records an allocation being moved from `old_ptr` to `new_ptr` by `realloc()`.
+/// The new record is owned by the same box as the old one.
+pub(crate) fn record_realloc(old_ptr: *const c_void, new_ptr: *const c_void, size: usize) {
+    let mut allocation = new_allocation(new_ptr, Some(size), Origin::Allocated);
+    let mut registry = registry();
+    if let Some(old) = registry.remove(&(old_ptr as usize)) {
+        allocation.owned = old.owned;
+    }
+    registry.insert(new_ptr as usize, allocation);
+}
+
+/// Returns a snapshot of all outstanding allocations, sorted by address.
+pub fn outstanding() -> Vec<Allocation> {
+    registry().values().cloned().collect()
+}
+
+/// Writes all outstanding allocations, one per line (followed by the backtrace if captured).
+pub fn dump<W: Write>(mut writer: W) -> io::Result<()> {
+    for allocation in outstanding() {
+        writeln!(writer, "{}", allocation)?;
+    }
+    Ok(())
+}
+
+/// Panics if there is any outstanding allocation, listing all of them in the panic message.
+///
+/// This is intended to be called at the end of a test.
+pub fn assert_no_leaks() {
+    let allocations = outstanding();
+    if !allocations.is_empty() {
+        let mut message = Vec::new();
+        dump(&mut message).unwrap();
+        panic!(
+            "{} outstanding allocation(s):\n{}",
+            allocations.len(),
+            String::from_utf8_lossy(&message)
+        );
+    }
+}
+
+#[cfg(test)]
+fn is_outstanding<T: ?Sized>(ptr: *const T) -> bool {
+    registry().contains_key(&(ptr as *const c_void as usize))
+}
+
+#[test]
+fn test_registry_alloc_and_free() {
+    let boxed = crate::MBox::new(42_u64);
+    let ptr = crate::MBox::as_ptr(&boxed);
+    assert!(is_outstanding(ptr));
+    assert_eq!(registry()[&(ptr as usize)].size(), Some(8));
+    assert_eq!(registry()[&(ptr as usize)].origin(), Origin::Allocated);
+
+    let ptr = crate::MBox::into_raw(boxed);
+    assert!(is_outstanding(ptr));
+    drop(unsafe { crate::MBox::from_raw(ptr) });
+    assert!(!is_outstanding(ptr));
+}
+
+#[test]
+fn test_registry_realloc() {
+    let mut vec = crate::MVec::with_capacity(1);
+    vec.push(1_u8);
+    let old_ptr = vec.as_ptr();
+    vec.extend(0..100);
+    let new_ptr = vec.as_ptr();
+    assert!(is_outstanding(new_ptr));
+    assert!(old_ptr == new_ptr || !is_outstanding(old_ptr));
+    drop(vec);
+    assert!(!is_outstanding(new_ptr));
+}
+
+#[test]
+fn test_registry_adopt() {
+    unsafe {
+        let ptr = libc::strdup(b"hello\0".as_ptr() as *const libc::c_char);
+        let string = crate::MString::from_raw_unchecked(ptr);
+        assert!(is_outstanding(ptr));
+        assert_eq!(registry()[&(ptr as usize)].origin(), Origin::Adopted);
+        assert!(outstanding()
+            .iter()
+            .any(|a| a.address() == ptr as usize && a.thread() == thread::current().id()));
+        drop(string);
+        assert!(!is_outstanding(ptr));
+    }
+}
+
+#[test]
+fn test_registry_dump() {
+    let leaked = crate::MBox::leak(crate::MBox::new(7_u16)) as *mut u16;
+    let mut output = Vec::new();
+    dump(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(&format!("{:#x} (2 bytes allocated on", leaked as usize)));
+    drop(unsafe { crate::MBox::from_raw(leaked) });
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+#[should_panic(expected = "is already owned by another box")]
+fn test_check_double_adoption() {
+    let boxed = crate::MBox::new(1_u32);
+    let ptr = crate::MBox::as_ptr(&boxed) as *mut u32;
+    drop(unsafe { crate::MBox::from_raw(ptr) });
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+fn test_check_readoption() {
+    let array = crate::MArray::from_slice(&[1_u8, 2, 3]);
+    let ptr = array.into_mbox_with_sentinel().into_raw_parts().0;
+    let array = unsafe { crate::MArray::from_raw(ptr) };
+    assert_eq!(&*array, &[1, 2, 3]);
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+#[should_panic(expected = "which is not aligned to 4 bytes")]
+fn test_check_misaligned() {
+    let boxed = crate::MBox::new(0_u64);
+    let ptr = unsafe { (crate::MBox::as_ptr(&boxed) as *mut u8).add(1) };
+    drop(unsafe { crate::MBox::from_raw(ptr as *mut u32) });
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+#[should_panic(expected = "which is not aligned to 4 bytes")]
+fn test_check_misaligned_slice() {
+    let boxed = crate::MBox::<[u64]>::from_slice(&[0, 0]);
+    let ptr = unsafe { (crate::MBox::as_ptr(&boxed) as *mut u8).add(2) };
+    drop(unsafe { crate::MBox::from_raw_parts(ptr as *mut u32, 2) });
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+#[should_panic(expected = "cannot adopt a null pointer")]
+fn test_check_null() {
+    drop(unsafe { crate::MString::from_raw_unchecked(std::ptr::null_mut()) });
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+#[should_panic(expected = "cannot adopt a null pointer")]
+fn test_check_null_lossy() {
+    drop(unsafe { crate::MString::from_raw_lossy(std::ptr::null_mut()) });
+}
+
+#[cfg(feature = "check_pointers")]
+#[test]
+#[should_panic(expected = "which was neither allocated nor adopted by mbox")]
+fn test_check_foreign_free() {
+    use crate::free::{Dealloc, LibcFree};
+    use std::ptr::NonNull;
+
+    let mut value = 0_u64;
+    unsafe { LibcFree.dealloc(NonNull::from(&mut value).cast()) };
+}
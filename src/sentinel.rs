@@ -1,148 +1,1178 @@
 //! Sentinel-terminated types.
 
+// recent versions of `libc` re-export `c_char` from `core::ffi`, which this lint mistakes for the
+// item stabilized in Rust 1.64.
+#![allow(clippy::incompatible_msrv)]
+
 use libc::{c_char, strlen};
 #[cfg(feature = "stable_deref_trait")]
 use stable_deref_trait::StableDeref;
 
-use std::borrow::{Borrow, BorrowMut};
-use std::convert::{AsMut, AsRef};
-use std::default::Default;
-#[cfg(feature = "std")]
-use std::ffi::CStr;
-use std::hash::{Hash, Hasher};
-use std::iter::once;
-use std::ops::{Deref, DerefMut};
-use std::ptr::{copy_nonoverlapping, null, null_mut, write};
-use std::str::Utf8Error;
+#[cfg(not(feature = "std"))]
+use alloc::borrow::ToOwned;
+#[cfg(not(feature = "std"))]
+use alloc::{borrow::Cow, string::String};
+use std::ascii::escape_default;
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+use std::borrow::{Borrow, BorrowMut};
+use std::cmp::Ordering;
+use std::convert::{AsMut, AsRef};
+use std::default::Default;
+#[cfg(feature = "std")]
+use std::ffi::CStr;
+use std::fmt::{Arguments, Debug, Display, Formatter, Result as FormatResult, Write};
+use std::hash::{Hash, Hasher};
+use std::iter::Extend;
+#[cfg(feature = "check_pointers")]
+use std::mem::align_of;
+use std::ops::{AddAssign, Deref, DerefMut};
+use std::ptr::{copy, copy_nonoverlapping, null, null_mut, write};
+use std::slice;
+use std::str::{from_utf8, from_utf8_unchecked, from_utf8_unchecked_mut, Utf8Error};
+
+use crate::free::{Dealloc, LibcFree};
+#[cfg(feature = "check_pointers")]
+use crate::internal::{check_aligned, check_non_null};
+use crate::internal::{gen_malloc, try_gen_malloc, AllocError};
+use crate::mbox::MBox;
+use crate::vec::{BitCopy, MVec};
+
+#[cfg(all(test, not(windows)))]
+use crate::internal::{DeallocCounter, DropCounter};
+
+/// Implemented for types which has a sentinel value.
+pub trait Sentinel: Eq {
+    /// Obtains the sentinel value.
+    const SENTINEL: Self;
+}
+
+impl<T> Sentinel for *const T {
+    const SENTINEL: Self = null();
+}
+
+impl<T> Sentinel for *mut T {
+    const SENTINEL: Self = null_mut();
+}
+
+impl<T: Eq> Sentinel for Option<T> {
+    const SENTINEL: Self = None;
+}
+
+macro_rules! impl_zero_for_sentinel {
+    ($($ty:ty)+) => {
+        $(impl Sentinel for $ty {
+            const SENTINEL: Self = 0;
+        })+
+    }
+}
+
+impl_zero_for_sentinel!(u8 i8 u16 i16 u32 i32 u64 i64 u128 i128 usize isize);
+
+/// Counts the number of items before the sentinel.
+///
+/// # Safety
+///
+/// The `base` must not be null, and must point to an initialized array terminated by
+/// `T::SENTINEL`.
+unsafe fn sentinel_len<T: Sentinel>(base: *const T) -> usize {
+    let mut len = 0;
+    while *base.add(len) != T::SENTINEL {
+        len += 1;
+    }
+    len
+}
+
+/// A `malloc`-backed array with an explicit sentinel at the end.
+pub struct MArray<T: Sentinel, D: Dealloc = LibcFree>(MBox<[T], D>);
+
+/// A `malloc`-backed null-terminated string (similar to `CString`).
+pub struct MString<D: Dealloc = LibcFree>(MBox<str, D>);
+
+/// A `malloc`-backed null-terminated byte string without any encoding requirement.
+///
+/// This is suitable for C strings which are not guaranteed to be UTF-8, e.g. file names or
+/// locale-encoded messages.
+pub struct MCString<D: Dealloc = LibcFree>(MArray<u8, D>);
+
+/// An error indicating that an `MCString` cannot be converted into an `MString` because it is not
+/// valid UTF-8 (similar to `std::ffi::IntoStringError`).
+pub struct IntoStringError<D: Dealloc = LibcFree> {
+    inner: MCString<D>,
+    error: Utf8Error,
+}
+
+/// An error indicating that an interior `'\0'` byte was found when creating a null-terminated
+/// string (similar to `std::ffi::NulError`).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NulError(usize, MBox<[u8]>);
+
+/// A growable `malloc`-backed null-terminated string (similar to `String`).
+///
+/// The buffer is always terminated by `'\0'` after every mutation, so it can be passed to C code
+/// at any time.
+#[derive(Clone)]
+pub struct MStringBuf(MVec<u8>);
+
+/// A borrowed null-terminated string (similar to `CStr`), which is the borrowed counterpart of
+/// `MString`.
+///
+/// This is useful for strings returned from C functions which must not be freed, e.g. `getenv()`
+/// or `strerror()`.
+#[repr(transparent)]
+pub struct MStr(str);
+
+/// A borrowed array with an explicit sentinel at the end, which is the borrowed counterpart of
+/// `MArray<T>`.
+pub struct MArrayRef<'a, T: Sentinel>(&'a [T]);
+
+impl<T: Sentinel> MArray<T> {
+    /// Constructs a new malloc-backed slice from a pointer to the null-terminated array.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be allocated via `malloc()`, `calloc()` or similar C functions that is
+    /// expected to be deallocated using `free()`. It must not be null. The content of the pointer
+    /// must be already initialized, and terminated by `T::SENTINEL`. The array's ownership is
+    /// passed into the result, and thus should not be used after this function returns.
+    pub unsafe fn from_raw(base: *mut T) -> MArray<T> {
+        Self::from_raw_in(base, LibcFree)
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> MArray<T, D> {
+    /// Constructs a new array from a pointer to the null-terminated array, which will be released
+    /// using the deallocator `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated by the allocator corresponding to `dealloc`. It must not be
+    /// null. The content of the pointer must be already initialized, and terminated by
+    /// `T::SENTINEL`. The array's ownership is passed into the result, and thus should not be used
+    /// after this function returns.
+    pub unsafe fn from_raw_in(base: *mut T, dealloc: D) -> MArray<T, D> {
+        #[cfg(feature = "check_pointers")]
+        {
+            check_non_null(base);
+            check_aligned(base, align_of::<T>());
+        }
+        let len = sentinel_len(base);
+        MArray(MBox::from_raw_parts_in(base, len + 1, dealloc))
+    }
+
+    /// Converts into an `MBox` including the sentinel.
+    pub fn into_mbox_with_sentinel(self) -> MBox<[T], D> {
+        self.0
+    }
+
+    /// Converts into an `MBox` excluding the sentinel.
+    pub fn into_mbox(self) -> MBox<[T], D> {
+        let (ptr, len, dealloc) = self.0.into_raw_parts_with_dealloc();
+        unsafe { MBox::from_raw_parts_in(ptr, len - 1, dealloc) }
+    }
+}
+
+impl<T: Sentinel + Clone> MArray<T> {
+    /// Creates a null-terminated array from the clone of a slice.
+    ///
+    /// With the `nightly` feature, this is a single `memcpy()` when `T` is a primitive type or a
+    /// raw pointer. Use `from_copy_slice()` to get the same on stable.
+    pub fn from_slice(slice: &[T]) -> MArray<T> {
+        Self::try_from_slice(slice).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Creates a null-terminated array from the clone of a slice. Returns an `AllocError` if the
+    /// memory cannot be allocated.
+    pub fn try_from_slice(slice: &[T]) -> Result<MArray<T>, AllocError> {
+        let len = slice
+            .len()
+            .checked_add(1)
+            .ok_or_else(AllocError::capacity_overflow)?;
+        let mut vec = MVec::try_with_capacity(len)?;
+        vec.extend_from_slice(slice);
+        vec.push(T::SENTINEL);
+        Ok(MArray(vec.into_boxed_slice()))
+    }
+}
+
+impl<T: Sentinel + BitCopy> MArray<T> {
+    /// Creates a null-terminated array by copying a slice using a single `memcpy()`.
+    pub fn from_copy_slice(slice: &[T]) -> MArray<T> {
+        Self::try_from_copy_slice(slice).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Creates a null-terminated array by copying a slice using a single `memcpy()`. Returns an
+    /// `AllocError` if the memory cannot be allocated.
+    pub fn try_from_copy_slice(slice: &[T]) -> Result<MArray<T>, AllocError> {
+        let len = slice
+            .len()
+            .checked_add(1)
+            .ok_or_else(AllocError::capacity_overflow)?;
+        let mut vec = MVec::try_with_capacity(len)?;
+        vec.extend_from_copy_slice(slice);
+        vec.push(T::SENTINEL);
+        Ok(MArray(vec.into_boxed_slice()))
+    }
+}
+
+impl MString {
+    /// Constructs a new malloc-backed string from a null-terminated C string.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated via `malloc()`, `calloc()` or similar C functions that is
+    /// expected to be deallocated using `free()`. It must not be null. The content of the string
+    /// must be already initialized, and terminated by `'\0'`. The string's ownership is passed into
+    /// the result, and thus should not be used after this function returns.
+    ///
+    /// The string must be valid UTF-8.
+    pub unsafe fn from_raw_unchecked(base: *mut c_char) -> MString {
+        Self::from_raw_unchecked_in(base, LibcFree)
+    }
+
+    /// Constructs a new malloc-backed string from a null-terminated C string. Errors with
+    /// `IntoStringError` if the string is not in valid UTF-8, from which the original bytes can be
+    /// recovered.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated via `malloc()`, `calloc()` or similar C functions that is
+    /// expected to be deallocated using `free()`. It must not be null. The content of the string
+    /// must be already initialized, and terminated by `'\0'`. The string's ownership is passed into
+    /// the result, and thus should not be used after this function returns.
+    pub unsafe fn from_raw(base: *mut c_char) -> Result<MString, IntoStringError> {
+        Self::from_raw_in(base, LibcFree)
+    }
+
+    /// Constructs a new malloc-backed string from a null-terminated C string, replacing any
+    /// invalid UTF-8 sequences with U+FFFD REPLACEMENT CHARACTER.
+    ///
+    /// If the string is already valid UTF-8, the buffer is reused without copying. Otherwise, a new
+    /// `malloc`ed buffer is allocated and `base` is freed.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated via `malloc()`, `calloc()` or similar C functions that is
+    /// expected to be deallocated using `free()`. It must not be null. The content of the string
+    /// must be already initialized, and terminated by `'\0'`. The string's ownership is passed into
+    /// the result, and thus should not be used after this function returns.
+    pub unsafe fn from_raw_lossy(base: *mut c_char) -> MString {
+        #[cfg(feature = "check_pointers")]
+        check_non_null(base);
+        let len = strlen(base);
+        let bytes = MBox::from_raw_parts(base as *mut u8, len + 1);
+        MString(MBox::from_utf8_lossy(bytes))
+    }
+
+    /// Creates a null-terminated string from formatted arguments, which can be created using the
+    /// `format_args!` macro. The output is written directly into a `malloc`ed buffer.
+    ///
+    /// Returns a `NulError` if the formatted string contains an interior `'\0'`, since the result
+    /// would be silently truncated when used as a C string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a formatting trait implementation returns an error.
+    pub fn from_fmt(args: Arguments) -> Result<MString, NulError> {
+        let mut buf = MStringBuf::new();
+        buf.write_fmt(args)
+            .expect("a formatting trait implementation returned an error");
+        match buf.bytes().position(|b| b == 0) {
+            Some(pos) => Err(NulError(pos, buf.into_mstring().into_bytes().into_mbox())),
+            None => Ok(buf.into_mstring()),
+        }
+    }
+
+    /// Creates a null-terminated string from the clone of a string. Returns an `AllocError` if the
+    /// memory cannot be allocated.
+    pub fn try_from_str(string: &str) -> Result<MString, AllocError> {
+        let len = string.len();
+        let alloc_len = len
+            .checked_add(1)
+            .ok_or_else(AllocError::capacity_overflow)?;
+        unsafe {
+            let ptr = try_gen_malloc(alloc_len)?.as_ptr();
+            copy_nonoverlapping(string.as_ptr(), ptr, len);
+            write(ptr.add(len), 0);
+            Ok(MString(MBox::from_raw_utf8_parts_unchecked(ptr, alloc_len)))
+        }
+    }
+}
+
+impl<D: Dealloc> MString<D> {
+    /// Constructs a new string from a null-terminated C string, which will be released using the
+    /// deallocator `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated by the allocator corresponding to `dealloc`. It must not be
+    /// null. The content of the string must be already initialized, and terminated by `'\0'`. The
+    /// string's ownership is passed into the result, and thus should not be used after this
+    /// function returns.
+    ///
+    /// The string must be valid UTF-8.
+    pub unsafe fn from_raw_unchecked_in(base: *mut c_char, dealloc: D) -> MString<D> {
+        #[cfg(feature = "check_pointers")]
+        check_non_null(base);
+        let len = strlen(base);
+        let bytes = MBox::from_raw_parts_in(base as *mut u8, len + 1, dealloc);
+        MString(MBox::from_utf8_unchecked(bytes))
+    }
+
+    /// Constructs a new string from a null-terminated C string, which will be released using the
+    /// deallocator `dealloc`. Errors with `IntoStringError` if the string is not in valid UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated by the allocator corresponding to `dealloc`. It must not be
+    /// null. The content of the string must be already initialized, and terminated by `'\0'`. The
+    /// string's ownership is passed into the result, and thus should not be used after this
+    /// function returns.
+    pub unsafe fn from_raw_in(
+        base: *mut c_char,
+        dealloc: D,
+    ) -> Result<MString<D>, IntoStringError<D>> {
+        MCString::from_raw_in(base, dealloc).into_string()
+    }
+
+    /// Converts into an `MArray` of bytes, including the sentinel.
+    pub fn into_bytes(self) -> MArray<u8, D> {
+        MArray(self.0.into_bytes())
+    }
+
+    /// Converts into an `MBox` including the sentinel.
+    pub fn into_mbox_with_sentinel(self) -> MBox<str, D> {
+        self.0
+    }
+
+    /// Converts into an `MBox` excluding the sentinel.
+    pub fn into_mbox(self) -> MBox<str, D> {
+        unsafe { MBox::from_utf8_unchecked(self.into_bytes().into_mbox()) }
+    }
+
+    /// Converts to a C string. This allows users to borrow an MString in FFI code.
+    #[cfg(feature = "std")]
+    pub fn as_c_str(&self) -> &CStr {
+        unsafe { CStr::from_bytes_with_nul_unchecked(self.0.as_bytes()) }
+    }
+
+    /// Obtains the raw bytes including the sentinel.
+    pub fn as_bytes_with_sentinel(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl MCString {
+    /// Constructs a new malloc-backed byte string from a null-terminated C string.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated via `malloc()`, `calloc()` or similar C functions that is
+    /// expected to be deallocated using `free()`. It must not be null. The content of the string
+    /// must be already initialized, and terminated by `'\0'`. The string's ownership is passed into
+    /// the result, and thus should not be used after this function returns.
+    pub unsafe fn from_raw(base: *mut c_char) -> MCString {
+        Self::from_raw_in(base, LibcFree)
+    }
+
+    /// Creates a null-terminated byte string from the clone of a byte slice. Returns a `NulError`
+    /// if the slice contains any `'\0'`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<MCString, NulError> {
+        match bytes.iter().position(|&b| b == 0) {
+            Some(pos) => Err(NulError(pos, MBox::from_slice(bytes))),
+            None => Ok(MCString(MArray::from_slice(bytes))),
+        }
+    }
+
+    /// Converts into an `MString`, replacing any invalid UTF-8 sequences with U+FFFD REPLACEMENT
+    /// CHARACTER. The buffer is reused if the content is already valid UTF-8.
+    pub fn into_string_lossy(self) -> MString {
+        MString(MBox::from_utf8_lossy(self.into_mbox_with_sentinel()))
+    }
+}
+
+impl<D: Dealloc> MCString<D> {
+    /// Constructs a new byte string from a null-terminated C string, which will be released using
+    /// the deallocator `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must be allocated by the allocator corresponding to `dealloc`. It must not be
+    /// null. The content of the string must be already initialized, and terminated by `'\0'`. The
+    /// string's ownership is passed into the result, and thus should not be used after this
+    /// function returns.
+    pub unsafe fn from_raw_in(base: *mut c_char, dealloc: D) -> MCString<D> {
+        MCString(MArray::from_raw_in(base as *mut u8, dealloc))
+    }
+
+    /// Obtains the raw bytes including the sentinel.
+    pub fn as_bytes_with_sentinel(&self) -> &[u8] {
+        &self.0 .0
+    }
+
+    /// Converts to a C string. This allows users to borrow an MCString in FFI code.
+    #[cfg(feature = "std")]
+    pub fn as_c_str(&self) -> &CStr {
+        unsafe { CStr::from_bytes_with_nul_unchecked(self.as_bytes_with_sentinel()) }
+    }
+
+    /// Yields a string slice if the content is valid UTF-8.
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        from_utf8(self)
+    }
+
+    /// Converts the content into a string, replacing any invalid UTF-8 sequences with U+FFFD.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self)
+    }
+
+    /// Converts into an `MString` without copying. Errors with `IntoStringError` if the content is
+    /// not valid UTF-8, which gives back the original byte string.
+    pub fn into_string(self) -> Result<MString<D>, IntoStringError<D>> {
+        match from_utf8(&self) {
+            Ok(_) => Ok(MString(unsafe {
+                MBox::from_utf8_unchecked(self.0.into_mbox_with_sentinel())
+            })),
+            Err(error) => Err(IntoStringError { inner: self, error }),
+        }
+    }
+
+    /// Converts into an `MArray` of bytes.
+    pub fn into_bytes(self) -> MArray<u8, D> {
+        self.0
+    }
+
+    /// Converts into an `MBox` including the sentinel.
+    pub fn into_mbox_with_sentinel(self) -> MBox<[u8], D> {
+        self.0.into_mbox_with_sentinel()
+    }
+
+    /// Converts into an `MBox` excluding the sentinel.
+    pub fn into_mbox(self) -> MBox<[u8], D> {
+        self.0.into_mbox()
+    }
+}
+
+impl<D: Dealloc> IntoStringError<D> {
+    /// Returns the original byte string which failed the conversion.
+    pub fn into_cstring(self) -> MCString<D> {
+        self.inner
+    }
+
+    /// Returns the original bytes which failed the conversion, including the sentinel.
+    pub fn into_bytes(self) -> MArray<u8, D> {
+        self.inner.into_bytes()
+    }
+
+    /// Returns the UTF-8 error which caused the conversion to fail.
+    pub fn utf8_error(&self) -> Utf8Error {
+        self.error
+    }
+}
+
+impl Clone for IntoStringError {
+    fn clone(&self) -> Self {
+        IntoStringError {
+            inner: self.inner.clone(),
+            error: self.error,
+        }
+    }
+}
+
+impl<D: Dealloc> PartialEq for IntoStringError<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && self.error == other.error
+    }
+}
+
+impl<D: Dealloc> Eq for IntoStringError<D> {}
+
+impl<D: Dealloc> Debug for IntoStringError<D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter
+            .debug_struct("IntoStringError")
+            .field("inner", &self.inner)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<D: Dealloc> Display for IntoStringError<D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        write!(
+            formatter,
+            "C string contained non-utf8 bytes: {}",
+            self.error
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: Dealloc> std::error::Error for IntoStringError<D> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<D: Dealloc> From<MString<D>> for MCString<D> {
+    fn from(string: MString<D>) -> MCString<D> {
+        MCString(string.into_bytes())
+    }
+}
+
+impl From<&str> for MString {
+    /// Creates a null-terminated string from the clone of a string.
+    fn from(string: &str) -> MString {
+        MString::try_from_str(string).unwrap_or_else(|e| e.handle())
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> Deref for MArray<T, D> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        let actual_len = self.0.len() - 1;
+        &self.0[..actual_len]
+    }
+}
+
+impl<D: Dealloc> Deref for MCString<D> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<D: Dealloc> Deref for MString<D> {
+    type Target = str;
+    fn deref(&self) -> &str {
+        let actual_len = self.0.len() - 1;
+        &self.0[..actual_len]
+    }
+}
+
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl<T: Sentinel, D: Dealloc> StableDeref for MArray<T, D> {}
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl<D: Dealloc> StableDeref for MString<D> {}
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl<D: Dealloc> StableDeref for MCString<D> {}
+
+impl<T: Sentinel, D: Dealloc> DerefMut for MArray<T, D> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        let actual_len = self.0.len() - 1;
+        &mut self.0[..actual_len]
+    }
+}
+
+impl<D: Dealloc> Hash for MString<D> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state);
+    }
+}
+
+impl<T: Sentinel + Hash, D: Dealloc> Hash for MArray<T, D> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state);
+    }
+}
+
+impl<D: Dealloc> DerefMut for MString<D> {
+    fn deref_mut(&mut self) -> &mut str {
+        let actual_len = self.0.len() - 1;
+        &mut self.0[..actual_len]
+    }
+}
+
+impl<T: Sentinel> Default for MArray<T> {
+    fn default() -> Self {
+        unsafe {
+            let arr = gen_malloc(1).as_ptr();
+            write(arr, T::SENTINEL);
+            MArray(MBox::from_raw_parts(arr, 1))
+        }
+    }
+}
+
+impl Default for MString {
+    fn default() -> Self {
+        unsafe {
+            let arr = gen_malloc(1).as_ptr();
+            write(arr, 0);
+            MString(MBox::from_raw_utf8_parts_unchecked(arr, 1))
+        }
+    }
+}
+
+impl<T: Sentinel + Clone> Clone for MArray<T> {
+    fn clone(&self) -> Self {
+        MArray(self.0.clone())
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> PartialEq for MArray<T, D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> Eq for MArray<T, D> {}
+
+impl<T: Sentinel + PartialOrd, D: Dealloc> PartialOrd for MArray<T, D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<T: Sentinel + Ord, D: Dealloc> Ord for MArray<T, D> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<T: Sentinel + Debug, D: Dealloc> Debug for MArray<T, D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter.debug_tuple("MArray").field(&self.0).finish()
+    }
+}
+
+impl Clone for MString {
+    fn clone(&self) -> Self {
+        MString(self.0.clone())
+    }
+}
+
+impl<D: Dealloc> PartialEq for MString<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<D: Dealloc> Eq for MString<D> {}
+
+impl<D: Dealloc> PartialOrd for MString<D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<D: Dealloc> Ord for MString<D> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<D: Dealloc> Debug for MString<D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter.debug_tuple("MString").field(&self.0).finish()
+    }
+}
+
+impl Clone for MCString {
+    fn clone(&self) -> Self {
+        MCString(self.0.clone())
+    }
+}
+
+impl Default for MCString {
+    fn default() -> Self {
+        MCString(MArray::default())
+    }
+}
+
+impl<D: Dealloc> PartialEq for MCString<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<D: Dealloc> Eq for MCString<D> {}
+
+impl<D: Dealloc> PartialOrd for MCString<D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<D: Dealloc> Ord for MCString<D> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<D: Dealloc> Hash for MCString<D> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl<D: Dealloc> Debug for MCString<D> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        formatter.write_char('"')?;
+        for &b in self.iter() {
+            for c in escape_default(b) {
+                formatter.write_char(c as char)?;
+            }
+        }
+        formatter.write_char('"')
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> AsRef<[T]> for MArray<T, D> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> AsMut<[T]> for MArray<T, D> {
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> Borrow<[T]> for MArray<T, D> {
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T: Sentinel, D: Dealloc> BorrowMut<[T]> for MArray<T, D> {
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<D: Dealloc> AsRef<str> for MString<D> {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl<D: Dealloc> AsMut<str> for MString<D> {
+    fn as_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+impl<D: Dealloc> Borrow<str> for MString<D> {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl<D: Dealloc> BorrowMut<str> for MString<D> {
+    fn borrow_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: Dealloc> AsRef<CStr> for MString<D> {
+    fn as_ref(&self) -> &CStr {
+        self.as_c_str()
+    }
+}
+
+impl<D: Dealloc> AsRef<[u8]> for MCString<D> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<D: Dealloc> Borrow<[u8]> for MCString<D> {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: Dealloc> AsRef<CStr> for MCString<D> {
+    fn as_ref(&self) -> &CStr {
+        self.as_c_str()
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&CStr> for MCString {
+    /// Creates a null-terminated byte string from the clone of a C string.
+    fn from(string: &CStr) -> MCString {
+        MCString(MArray::from_slice(string.to_bytes()))
+    }
+}
+
+impl NulError {
+    /// Returns the position of the `'\0'` byte in the bytes.
+    pub fn nul_position(&self) -> usize {
+        self.0
+    }
+
+    /// Returns the bytes which contained the interior `'\0'`, excluding the sentinel.
+    pub fn into_bytes(self) -> MBox<[u8]> {
+        self.1
+    }
+}
+
+impl Display for NulError {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        write!(
+            formatter,
+            "nul byte found in provided data at position: {}",
+            self.0
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NulError {}
+
+/// Creates an `MString` using interpolation of runtime expressions, similar to `format!`.
+///
+/// The output is written directly into a `malloc`ed null-terminated buffer, so the result can be
+/// handed to C code which will `free()` it, similar to `asprintf()`.
+///
+/// This macro evaluates to `Result<MString, NulError>`, which is an `Err` if the formatted string
+/// contains an interior `'\0'`.
+///
+/// ```rust
+/// let string = mbox::mformat!("{}-{:02}", "a", 7).unwrap();
+/// assert_eq!(string.as_bytes_with_sentinel(), b"a-07\0");
+///
+/// assert!(mbox::mformat!("{}", "a\0b").is_err());
+/// ```
+#[macro_export]
+macro_rules! mformat {
+    ($($arg:tt)*) => {
+        $crate::MString::from_fmt(format_args!($($arg)*))
+    };
+}
+
+impl MStringBuf {
+    /// Creates a new empty string.
+    pub fn new() -> MStringBuf {
+        MStringBuf::with_capacity(0)
+    }
+
+    /// Creates a new empty string with at least the specified capacity, excluding the sentinel.
+    pub fn with_capacity(capacity: usize) -> MStringBuf {
+        let mut vec = MVec::with_capacity(capacity.saturating_add(1));
+        vec.push(0);
+        MStringBuf(vec)
+    }
+
+    /// Returns the number of bytes the string can hold without reallocating, excluding the
+    /// sentinel.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity() - 1
+    }
+
+    /// Grows the capacity to cover all usable space in the allocation, as reported by
+    /// `malloc_usable_size()`. See `MVec::adopt_usable_capacity()` for details.
+    pub fn adopt_usable_capacity(&mut self) {
+        self.0.adopt_usable_capacity();
+    }
+
+    /// Reserves capacity for at least `additional` more bytes.
+    pub fn reserve(&mut self, additional: usize) {
+        self.0.reserve(additional);
+    }
+
+    /// Reserves capacity for at least `additional` more bytes. Returns an `AllocError` if the
+    /// memory cannot be allocated, in which case the string is left unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        self.0.try_reserve(additional)
+    }
+
+    /// Shrinks the capacity of the string to its length using `realloc()`.
+    pub fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit();
+    }
+
+    /// Appends a string slice to the end of this string.
+    pub fn push_str(&mut self, string: &str) {
+        self.0.reserve(string.len());
+        self.0.pop();
+        self.0.extend_from_slice(string.as_bytes());
+        self.0.push(0);
+    }
+
+    /// Appends a character to the end of this string.
+    pub fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Inserts a string slice at the byte position `idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is larger than the string's length, or if it does not lie on a `char`
+    /// boundary.
+    pub fn insert_str(&mut self, idx: usize, string: &str) {
+        assert!(self.is_char_boundary(idx));
+        let amt = string.len();
+        let len = self.0.len();
+        self.0.reserve(amt);
+        // SAFETY: the capacity reserved above is enough to hold `len + amt` bytes. `idx` is a char
+        // boundary, so inserting a UTF-8 string there keeps the content valid UTF-8.
+        unsafe {
+            let ptr = self.0.as_mut_ptr();
+            copy(ptr.add(idx), ptr.add(idx + amt), len - idx);
+            copy_nonoverlapping(string.as_ptr(), ptr.add(idx), amt);
+            self.0.set_len(len + amt);
+        }
+    }
+
+    /// Inserts a character at the byte position `idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is larger than the string's length, or if it does not lie on a `char`
+    /// boundary.
+    pub fn insert(&mut self, idx: usize, ch: char) {
+        self.insert_str(idx, ch.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Shortens this string to the specified length. The capacity is not changed.
+    ///
+    /// If `new_len` is greater than or equal to the current length, this has no effect.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` does not lie on a `char` boundary.
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len < self.len() {
+            assert!(self.is_char_boundary(new_len));
+            self.0.truncate(new_len);
+            self.0.push(0);
+        }
+    }
+
+    /// Truncates this string, removing all contents. The capacity is not changed.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Extracts a string slice containing the entire string, excluding the sentinel.
+    pub fn as_str(&self) -> &str {
+        self
+    }
+
+    /// Converts to a C string. This allows users to borrow an MStringBuf in FFI code.
+    #[cfg(feature = "std")]
+    pub fn as_c_str(&self) -> &CStr {
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.0) }
+    }
+
+    /// Obtains the raw bytes including the sentinel.
+    pub fn as_bytes_with_sentinel(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Converts into an `MString`. The excess capacity is kept in the allocation.
+    pub fn into_mstring(self) -> MString {
+        let bytes = self.0.into_boxed_slice();
+        // SAFETY: the content is always valid UTF-8 terminated by a sentinel.
+        unsafe { MString(MBox::from_utf8_unchecked(bytes)) }
+    }
+}
+
+impl Deref for MStringBuf {
+    type Target = str;
+    fn deref(&self) -> &str {
+        let actual_len = self.0.len() - 1;
+        // SAFETY: the content is always valid UTF-8.
+        unsafe { from_utf8_unchecked(&self.0[..actual_len]) }
+    }
+}
+
+impl DerefMut for MStringBuf {
+    fn deref_mut(&mut self) -> &mut str {
+        let actual_len = self.0.len() - 1;
+        // SAFETY: the content is always valid UTF-8.
+        unsafe { from_utf8_unchecked_mut(&mut self.0[..actual_len]) }
+    }
+}
+
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl StableDeref for MStringBuf {}
+
+impl Default for MStringBuf {
+    fn default() -> Self {
+        MStringBuf::new()
+    }
+}
+
+impl From<&str> for MStringBuf {
+    /// Creates a growable null-terminated string from the clone of a string.
+    fn from(string: &str) -> MStringBuf {
+        let mut buf = MStringBuf::with_capacity(string.len());
+        buf.push_str(string);
+        buf
+    }
+}
+
+impl From<MString> for MStringBuf {
+    /// Converts an `MString` into a growable string without reallocating.
+    fn from(string: MString) -> MStringBuf {
+        MStringBuf(MVec::from(string.0.into_bytes()))
+    }
+}
+
+impl From<MStringBuf> for MString {
+    fn from(string: MStringBuf) -> MString {
+        string.into_mstring()
+    }
+}
+
+impl Debug for MStringBuf {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Debug::fmt(self.as_str(), formatter)
+    }
+}
 
-use crate::internal::gen_malloc;
-use crate::mbox::MBox;
+impl Display for MStringBuf {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Display::fmt(self.as_str(), formatter)
+    }
+}
 
-#[cfg(all(test, not(windows)))]
-use crate::internal::DropCounter;
+impl PartialEq for MStringBuf {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
 
-/// Implemented for types which has a sentinel value.
-pub trait Sentinel: Eq {
-    /// Obtains the sentinel value.
-    const SENTINEL: Self;
+impl Eq for MStringBuf {}
+
+impl PartialOrd for MStringBuf {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
-impl<T> Sentinel for *const T {
-    const SENTINEL: Self = null();
+impl Ord for MStringBuf {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
 }
 
-impl<T> Sentinel for *mut T {
-    const SENTINEL: Self = null_mut();
+impl Hash for MStringBuf {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
 }
 
-impl<T: Eq> Sentinel for Option<T> {
-    const SENTINEL: Self = None;
+impl AsRef<str> for MStringBuf {
+    fn as_ref(&self) -> &str {
+        self
+    }
 }
 
-macro_rules! impl_zero_for_sentinel {
-    ($($ty:ty)+) => {
-        $(impl Sentinel for $ty {
-            const SENTINEL: Self = 0;
-        })+
+impl AsMut<str> for MStringBuf {
+    fn as_mut(&mut self) -> &mut str {
+        self
     }
 }
 
-impl_zero_for_sentinel!(u8 i8 u16 i16 u32 i32 u64 i64 u128 i128 usize isize);
+impl Borrow<str> for MStringBuf {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
 
-/// A `malloc`-backed array with an explicit sentinel at the end.
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub struct MArray<T: Sentinel>(MBox<[T]>);
+impl BorrowMut<str> for MStringBuf {
+    fn borrow_mut(&mut self) -> &mut str {
+        self
+    }
+}
 
-/// A `malloc`-backed null-terminated string (similar to `CString`).
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub struct MString(MBox<str>);
+#[cfg(feature = "std")]
+impl AsRef<CStr> for MStringBuf {
+    fn as_ref(&self) -> &CStr {
+        self.as_c_str()
+    }
+}
 
-impl<T: Sentinel> MArray<T> {
-    /// Constructs a new malloc-backed slice from a pointer to the null-terminated array.
-    ///
-    /// # Safety
-    ///
-    /// The `ptr` must be allocated via `malloc()`, `calloc()` or similar C functions that is
-    /// expected to be deallocated using `free()`. It must not be null. The content of the pointer
-    /// must be already initialized, and terminated by `T::SENTINEL`. The array's ownership is
-    /// passed into the result, and thus should not be used after this function returns.
-    pub unsafe fn from_raw(base: *mut T) -> MArray<T> {
-        let mut len = 0;
-        while *base.add(len) != T::SENTINEL {
-            len += 1;
+impl Extend<char> for MStringBuf {
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for ch in iter {
+            self.push(ch);
         }
-        MArray(MBox::from_raw_parts(base, len + 1))
     }
+}
 
-    /// Converts into an `MBox` including the sentinel.
-    pub fn into_mbox_with_sentinel(self) -> MBox<[T]> {
-        self.0
+impl<'a> Extend<&'a char> for MStringBuf {
+    fn extend<I: IntoIterator<Item = &'a char>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned());
     }
+}
 
-    /// Converts into an `MBox` excluding the sentinel.
-    pub fn into_mbox(self) -> MBox<[T]> {
-        let (ptr, len) = self.0.into_raw_parts();
-        unsafe { MBox::from_raw_parts(ptr, len - 1) }
+impl<'a> Extend<&'a str> for MStringBuf {
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for string in iter {
+            self.push_str(string);
+        }
     }
 }
 
-impl<T: Sentinel + Clone> MArray<T> {
-    /// Creates a null-terminated array from the clone of a slice.
-    pub fn from_slice(slice: &[T]) -> MArray<T> {
-        MArray(slice.iter().cloned().chain(once(T::SENTINEL)).collect())
+impl AddAssign<&str> for MStringBuf {
+    fn add_assign(&mut self, other: &str) {
+        self.push_str(other);
     }
 }
 
-impl MString {
-    /// Constructs a new malloc-backed string from a null-terminated C string.
+impl Write for MStringBuf {
+    fn write_str(&mut self, string: &str) -> FormatResult {
+        self.push_str(string);
+        Ok(())
+    }
+
+    fn write_char(&mut self, ch: char) -> FormatResult {
+        self.push(ch);
+        Ok(())
+    }
+}
+
+//{{{ Borrowed views ------------------------------------------------------------------------------
+
+impl MStr {
+    /// Wraps a borrowed null-terminated C string. Errors with `Utf8Error` if the string is not in
+    /// valid UTF-8.
     ///
     /// # Safety
     ///
-    /// The `base` must be allocated via `malloc()`, `calloc()` or similar C functions that is
-    /// expected to be deallocated using `free()`. It must not be null. The content of the string
-    /// must be already initialized, and terminated by `'\0'`. The string's ownership is passed into
-    /// the result, and thus should not be used after this function returns.
-    ///
-    /// The string must be valid UTF-8.
-    pub unsafe fn from_raw_unchecked(base: *mut c_char) -> MString {
-        let len = strlen(base);
-        MString(MBox::from_raw_utf8_parts_unchecked(
-            base as *mut u8,
-            len + 1,
-        ))
+    /// The `ptr` must not be null. The content of the string must be already initialized, and
+    /// terminated by `'\0'`. The memory must not be mutated or freed for the chosen lifetime `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *const c_char) -> Result<&'a MStr, Utf8Error> {
+        let len = strlen(ptr);
+        let bytes = slice::from_raw_parts(ptr as *const u8, len + 1);
+        from_utf8(bytes)?;
+        Ok(Self::from_str_with_sentinel(from_utf8_unchecked(bytes)))
     }
 
-    /// Constructs a new malloc-backed string from a null-terminated C string. Errors with
-    /// `Utf8Error` if the string is not in valid UTF-8.
+    /// Wraps a borrowed null-terminated C string without checking if it is valid UTF-8.
     ///
     /// # Safety
     ///
-    /// The `base` must be allocated via `malloc()`, `calloc()` or similar C functions that is
-    /// expected to be deallocated using `free()`. It must not be null. The content of the string
-    /// must be already initialized, and terminated by `'\0'`. The string's ownership is passed into
-    /// the result, and thus should not be used after this function returns.
-    pub unsafe fn from_raw(base: *mut c_char) -> Result<MString, Utf8Error> {
-        let len = strlen(base);
-        let mbox = MBox::from_raw_utf8_parts(base as *mut u8, len + 1)?;
-        Ok(MString(mbox))
-    }
-
-    pub fn into_bytes(self) -> MArray<u8> {
-        MArray(self.0.into_bytes())
+    /// Same as `from_ptr()`. Additionally, the string must be valid UTF-8.
+    pub unsafe fn from_ptr_unchecked<'a>(ptr: *const c_char) -> &'a MStr {
+        let len = strlen(ptr);
+        let bytes = slice::from_raw_parts(ptr as *const u8, len + 1);
+        Self::from_str_with_sentinel(from_utf8_unchecked(bytes))
     }
 
-    /// Converts into an `MBox` including the sentinel.
-    pub fn into_mbox_with_sentinel(self) -> MBox<str> {
-        self.0
+    /// Wraps a string which already ends with `'\0'`.
+    unsafe fn from_str_with_sentinel(string: &str) -> &MStr {
+        &*(string as *const str as *const MStr)
     }
 
-    /// Converts into an `MBox` excluding the sentinel.
-    pub fn into_mbox(self) -> MBox<str> {
-        unsafe { MBox::from_utf8_unchecked(self.into_bytes().into_mbox()) }
+    /// Obtains the pointer to the start of the string, which can be passed to C functions.
+    pub fn as_ptr(&self) -> *const c_char {
+        self.0.as_ptr() as *const c_char
     }
 
-    /// Converts to a C string. This allows users to borrow an MString in FFI code.
-    #[cfg(all(feature = "std"))]
+    /// Converts to a C string.
+    #[cfg(feature = "std")]
     pub fn as_c_str(&self) -> &CStr {
         unsafe { CStr::from_bytes_with_nul_unchecked(self.0.as_bytes()) }
     }
@@ -153,143 +1183,144 @@ impl MString {
     }
 }
 
-impl From<&str> for MString {
-    /// Creates a null-terminated string from the clone of a string.
-    fn from(string: &str) -> MString {
-        unsafe {
-            let len = string.len();
-            let ptr = gen_malloc(len + 1).as_ptr();
-            copy_nonoverlapping(string.as_ptr(), ptr, len);
-            write(ptr.add(len), 0);
-            MString(MBox::from_raw_utf8_parts_unchecked(ptr, len + 1))
-        }
+impl Deref for MStr {
+    type Target = str;
+    fn deref(&self) -> &str {
+        let actual_len = self.0.len() - 1;
+        &self.0[..actual_len]
     }
 }
 
-impl<T: Sentinel> Deref for MArray<T> {
-    type Target = [T];
-    fn deref(&self) -> &[T] {
-        let actual_len = self.0.len() - 1;
-        &self.0[..actual_len]
+impl ToOwned for MStr {
+    type Owned = MString;
+    fn to_owned(&self) -> MString {
+        MString::from(&**self)
     }
 }
 
-impl Deref for MString {
-    type Target = str;
-    fn deref(&self) -> &str {
-        let actual_len = self.0.len() - 1;
-        &self.0[..actual_len]
+impl Debug for MStr {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Debug::fmt(&**self, formatter)
     }
 }
 
-#[cfg(feature = "stable_deref_trait")]
-unsafe impl<T: Sentinel> StableDeref for MArray<T> {}
-#[cfg(feature = "stable_deref_trait")]
-unsafe impl StableDeref for MString {}
+impl Display for MStr {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Display::fmt(&**self, formatter)
+    }
+}
 
-impl<T: Sentinel> DerefMut for MArray<T> {
-    fn deref_mut(&mut self) -> &mut [T] {
-        let actual_len = self.0.len() - 1;
-        &mut self.0[..actual_len]
+impl PartialEq for MStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
     }
 }
 
-#[allow(clippy::derive_hash_xor_eq)]
-impl Hash for MString {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.deref().hash(state);
+impl Eq for MStr {}
+
+impl PartialOrd for MStr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MStr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
     }
 }
 
-#[allow(clippy::derive_hash_xor_eq)]
-impl<T: Sentinel + Hash> Hash for MArray<T> {
+impl Hash for MStr {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.deref().hash(state);
     }
 }
 
-impl DerefMut for MString {
-    fn deref_mut(&mut self) -> &mut str {
-        let actual_len = self.0.len() - 1;
-        &mut self.0[..actual_len]
+impl AsRef<str> for MStr {
+    fn as_ref(&self) -> &str {
+        self
     }
 }
 
-impl<T: Sentinel> Default for MArray<T> {
-    fn default() -> Self {
-        unsafe {
-            let arr = gen_malloc(1).as_ptr();
-            write(arr, T::SENTINEL);
-            MArray(MBox::from_raw_parts(arr, 1))
-        }
+#[cfg(feature = "std")]
+impl AsRef<CStr> for MStr {
+    fn as_ref(&self) -> &CStr {
+        self.as_c_str()
     }
 }
 
-impl Default for MString {
-    fn default() -> Self {
-        unsafe {
-            let arr = gen_malloc(1).as_ptr();
-            write(arr, 0);
-            MString(MBox::from_raw_utf8_parts_unchecked(arr, 1))
-        }
+impl<D: Dealloc> Borrow<MStr> for MString<D> {
+    fn borrow(&self) -> &MStr {
+        unsafe { MStr::from_str_with_sentinel(&self.0) }
     }
 }
 
-impl<T: Sentinel> AsRef<[T]> for MArray<T> {
-    fn as_ref(&self) -> &[T] {
-        self
+impl<D: Dealloc> AsRef<MStr> for MString<D> {
+    fn as_ref(&self) -> &MStr {
+        self.borrow()
     }
 }
 
-impl<T: Sentinel> AsMut<[T]> for MArray<T> {
-    fn as_mut(&mut self) -> &mut [T] {
-        self
+impl<'a, T: Sentinel> MArrayRef<'a, T> {
+    /// Wraps a borrowed array terminated by `T::SENTINEL`.
+    ///
+    /// # Safety
+    ///
+    /// The `base` must not be null. The content of the array must be already initialized, and
+    /// terminated by `T::SENTINEL`. The memory must not be mutated or freed for the chosen lifetime
+    /// `'a`.
+    pub unsafe fn from_ptr(base: *const T) -> MArrayRef<'a, T> {
+        let len = sentinel_len(base);
+        MArrayRef(slice::from_raw_parts(base, len + 1))
     }
-}
 
-impl<T: Sentinel> Borrow<[T]> for MArray<T> {
-    fn borrow(&self) -> &[T] {
-        self
+    /// Obtains the slice including the sentinel.
+    pub fn as_slice_with_sentinel(&self) -> &'a [T] {
+        self.0
     }
-}
 
-impl<T: Sentinel> BorrowMut<[T]> for MArray<T> {
-    fn borrow_mut(&mut self) -> &mut [T] {
-        self
+    /// Obtains the slice excluding the sentinel.
+    pub fn as_slice(&self) -> &'a [T] {
+        &self.0[..(self.0.len() - 1)]
     }
 }
 
-impl AsRef<str> for MString {
-    fn as_ref(&self) -> &str {
-        self
+impl<'a, T: Sentinel + Clone> MArrayRef<'a, T> {
+    /// Creates an owned `malloc`-backed array from the clone of the content.
+    pub fn to_owned(&self) -> MArray<T> {
+        MArray::from_slice(self)
     }
 }
 
-impl AsMut<str> for MString {
-    fn as_mut(&mut self) -> &mut str {
-        self
+impl<'a, T: Sentinel> Clone for MArrayRef<'a, T> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-impl Borrow<str> for MString {
-    fn borrow(&self) -> &str {
-        self
+impl<'a, T: Sentinel> Copy for MArrayRef<'a, T> {}
+
+impl<'a, T: Sentinel> Deref for MArrayRef<'a, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.as_slice()
     }
 }
 
-impl BorrowMut<str> for MString {
-    fn borrow_mut(&mut self) -> &mut str {
+impl<'a, T: Sentinel> AsRef<[T]> for MArrayRef<'a, T> {
+    fn as_ref(&self) -> &[T] {
         self
     }
 }
 
-#[cfg(feature = "std")]
-impl AsRef<CStr> for MString {
-    fn as_ref(&self) -> &CStr {
-        self.as_c_str()
+impl<'a, T: Sentinel + Debug> Debug for MArrayRef<'a, T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        Debug::fmt(&**self, formatter)
     }
 }
 
+//}}}
+
 #[test]
 fn test_array() {
     unsafe {
@@ -308,6 +1339,21 @@ fn test_array() {
     }
 }
 
+#[test]
+fn test_array_from_slice() {
+    let items = [b"a\0".as_ptr(), b"b\0".as_ptr(), b"c\0".as_ptr()];
+    let array = MArray::from_slice(&items);
+    assert_eq!(&*array, &items);
+    assert_eq!(array.into_mbox_with_sentinel()[3], null());
+
+    let empty = MArray::<u32>::from_slice(&[]);
+    assert_eq!(&*empty.into_mbox_with_sentinel(), &[0]);
+
+    let array = MArray::from_copy_slice(&items);
+    assert_eq!(&*array, &items);
+    assert_eq!(array.into_mbox_with_sentinel()[3], null());
+}
+
 #[cfg(not(windows))]
 #[test]
 fn test_array_with_drop() {
@@ -349,8 +1395,11 @@ fn test_non_utf8_string() {
         *src.offset(0) = -1i8 as c_char;
         *src.offset(1) = 0;
 
-        let string = MString::from_raw(src);
-        assert!(string.is_err());
+        let err = MString::from_raw(src).unwrap_err();
+        assert_eq!(err.utf8_error().valid_up_to(), 0);
+        let bytes = err.into_bytes();
+        assert_eq!(bytes.as_ptr(), src as *const u8);
+        assert_eq!(&*bytes, b"\xff");
 
         let src2 = gen_malloc::<c_char>(2).as_ptr();
         *src2.offset(0) = 1;
@@ -361,6 +1410,30 @@ fn test_non_utf8_string() {
     }
 }
 
+#[test]
+fn test_string_from_raw_lossy() {
+    unsafe {
+        let src = gen_malloc::<c_char>(3).as_ptr();
+        *src.offset(0) = -1i8 as c_char;
+        *src.offset(1) = b'a' as c_char;
+        *src.offset(2) = 0;
+
+        let string = MString::from_raw_lossy(src);
+        assert_eq!(string.as_bytes_with_sentinel(), "\u{fffd}a\0".as_bytes());
+
+        let src2 = gen_malloc::<c_char>(2).as_ptr();
+        *src2.offset(0) = b'b' as c_char;
+        *src2.offset(1) = 0;
+
+        let string2 = MString::from_raw_lossy(src2);
+        assert_eq!(string2.as_ptr(), src2 as *const u8);
+        assert_eq!(&*string2, "b");
+    }
+
+    let string = MCString::from_bytes(b"\xfe").unwrap().into_string_lossy();
+    assert_eq!(string.as_bytes_with_sentinel(), "\u{fffd}\0".as_bytes());
+}
+
 #[cfg(feature = "std")]
 #[test]
 fn test_c_str() {
@@ -402,6 +1475,15 @@ fn test_default_array() {
     assert_eq!(arr.into_mbox_with_sentinel(), MBox::from_slice(&[0u64]));
 }
 
+#[test]
+fn test_try_from() {
+    let array = MArray::try_from_slice(b"abc").unwrap();
+    assert_eq!(array.into_mbox_with_sentinel(), MBox::from_slice(b"abc\0"));
+
+    let string = MString::try_from_str("abc").unwrap();
+    assert_eq!(string.as_bytes_with_sentinel(), b"abc\0");
+}
+
 #[test]
 fn test_default_string() {
     let string = MString::default();
@@ -445,3 +1527,195 @@ fn test_hash_array() {
     assert!(!hs.contains(&MArray::from_slice(b"ef")));
     assert!(hs.contains(&MArray::from_slice(b"a")));
 }
+
+#[test]
+fn test_string_buf() {
+    let mut string = MStringBuf::new();
+    assert_eq!(string.as_bytes_with_sentinel(), b"\0");
+
+    string.push_str("abc");
+    string.push('一');
+    string += "def";
+    string.insert_str(1, "xy");
+    string.insert(0, '>');
+    assert_eq!(&*string, ">axybc一def");
+    assert_eq!(string.as_bytes_with_sentinel(), ">axybc一def\0".as_bytes());
+
+    string.truncate(9);
+    assert_eq!(string.as_bytes_with_sentinel(), ">axybc一\0".as_bytes());
+    string.truncate(100);
+    assert_eq!(&*string, ">axybc一");
+
+    string.extend("gh".chars());
+    string.extend(["ij", "k"].iter().cloned());
+    assert_eq!(&*string, ">axybc一ghijk");
+
+    string.clear();
+    assert_eq!(string.as_bytes_with_sentinel(), b"\0");
+}
+
+#[test]
+#[should_panic(expected = "assertion failed: self.is_char_boundary(new_len)")]
+fn test_string_buf_truncate_not_char_boundary() {
+    let mut string = MStringBuf::from("一");
+    string.truncate(1);
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+#[test]
+fn test_string_buf_adopt_usable_capacity() {
+    let mut string = MStringBuf::from("abc");
+    string.adopt_usable_capacity();
+    let cap = string.capacity();
+    assert!(cap >= 3);
+    let ptr = string.as_ptr();
+    for _ in 3..cap {
+        string.push('!');
+    }
+    assert_eq!(string.as_ptr(), ptr);
+    assert_eq!(string.len(), cap);
+}
+
+#[test]
+fn test_string_buf_write() {
+    let mut string = MStringBuf::with_capacity(4);
+    assert!(string.capacity() >= 4);
+    let name = "abc";
+    write!(string, "{}-{:03}", name, 7).unwrap();
+    assert_eq!(string.as_bytes_with_sentinel(), b"abc-007\0");
+
+    let string = MString::from(string);
+    assert_eq!(string.as_bytes_with_sentinel(), b"abc-007\0");
+
+    let mut string = MStringBuf::from(string);
+    string.push('!');
+    assert_eq!(
+        string.into_mstring().as_bytes_with_sentinel(),
+        b"abc-007!\0"
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_string_buf_c_str() {
+    let mut string = MStringBuf::from("a");
+    string.push('b');
+    assert_eq!(string.as_c_str(), unsafe {
+        CStr::from_ptr(b"ab\0".as_ptr() as *const c_char)
+    });
+}
+
+#[test]
+fn test_mformat() {
+    let string = mformat!("{}{}", 1, "二").unwrap();
+    assert_eq!(string.as_bytes_with_sentinel(), "1二\0".as_bytes());
+
+    let string = mformat!("").unwrap();
+    assert_eq!(string.as_bytes_with_sentinel(), b"\0");
+
+    let err = mformat!("ab{}c", '\0').unwrap_err();
+    assert_eq!(err.nul_position(), 2);
+    assert_eq!(&*err.into_bytes(), b"ab\0c");
+}
+
+#[test]
+fn test_c_string() {
+    unsafe {
+        let src = gen_malloc::<c_char>(3).as_ptr();
+        *src.offset(0) = b'a' as c_char;
+        *src.offset(1) = -1i8 as c_char;
+        *src.offset(2) = 0;
+
+        let string = MCString::from_raw(src);
+        assert_eq!(&*string, b"a\xff");
+        assert_eq!(string.as_bytes_with_sentinel(), b"a\xff\0");
+        assert!(string.to_str().is_err());
+        assert_eq!(&*string.to_string_lossy(), "a\u{fffd}");
+
+        let err = string.into_string().unwrap_err();
+        assert_eq!(err.utf8_error().valid_up_to(), 1);
+        assert_eq!(&*err.into_cstring(), b"a\xff");
+    }
+
+    let string = MCString::from_bytes(b"xyz").unwrap();
+    assert_eq!(string.to_str(), Ok("xyz"));
+    assert_eq!(
+        string.into_string().unwrap().as_bytes_with_sentinel(),
+        b"xyz\0"
+    );
+
+    let err = MCString::from_bytes(b"x\0z").unwrap_err();
+    assert_eq!(err.nul_position(), 1);
+    assert_eq!(&*err.into_bytes(), b"x\0z");
+
+    assert_eq!(MCString::default().as_bytes_with_sentinel(), b"\0");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_c_string_c_str() {
+    let string = MCString::from(CStr::from_bytes_with_nul(b"a\xffb\0").unwrap());
+    assert_eq!(string.as_c_str().to_bytes(), b"a\xffb");
+    assert_eq!(format!("{:?}", string), "\"a\\xffb\"");
+}
+
+#[test]
+fn test_borrowed_str() {
+    let src = b"abc\0";
+    let string = unsafe { MStr::from_ptr(src.as_ptr() as *const c_char) }.unwrap();
+    assert_eq!(&**string, "abc");
+    assert_eq!(string.as_bytes_with_sentinel(), b"abc\0");
+    assert_eq!(string.as_ptr(), src.as_ptr() as *const c_char);
+
+    let owned: MString = string.to_owned();
+    assert_eq!(owned.as_bytes_with_sentinel(), b"abc\0");
+    assert!(Borrow::<MStr>::borrow(&owned) == string);
+
+    let src = b"\xff\0";
+    assert!(unsafe { MStr::from_ptr(src.as_ptr() as *const c_char) }.is_err());
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_borrowed_array() {
+    let src = [1u32, 2, 3, 0, 4];
+    let array = unsafe { MArrayRef::from_ptr(src.as_ptr()) };
+    assert_eq!(&*array, &[1, 2, 3]);
+    assert_eq!(array.as_slice_with_sentinel(), &[1, 2, 3, 0]);
+
+    let owned = array.to_owned();
+    assert_eq!(&*owned, &[1, 2, 3]);
+    assert_eq!(owned.into_mbox_with_sentinel().len(), 4);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_custom_dealloc() {
+    let dealloc = DeallocCounter::default();
+    unsafe {
+        let src = gen_malloc::<c_char>(3).as_ptr();
+        copy_nonoverlapping(b"ab\0".as_ptr() as *const c_char, src, 3);
+        let string = MString::from_raw_in(src, dealloc.clone()).unwrap();
+        assert_eq!(&*string, "ab");
+        let mbox = string.into_mbox();
+        assert_eq!(&*mbox, "ab");
+        dealloc.assert_eq(0);
+        drop(mbox);
+        dealloc.assert_eq(1);
+
+        let src = gen_malloc::<c_char>(2).as_ptr();
+        copy_nonoverlapping(b"\xff\0".as_ptr() as *const c_char, src, 2);
+        let err = MString::from_raw_in(src, dealloc.clone()).unwrap_err();
+        let bytes = err.into_cstring();
+        assert_eq!(&*bytes, b"\xff");
+        drop(bytes);
+        dealloc.assert_eq(2);
+
+        let src = gen_malloc::<u32>(3).as_ptr();
+        copy_nonoverlapping([7, 8, 0].as_ptr(), src, 3);
+        let array = MArray::from_raw_in(src, dealloc.clone());
+        assert_eq!(&*array, &[7, 8]);
+        assert_eq!(array.into_mbox().into_iter().sum::<u32>(), 15);
+        dealloc.assert_eq(3);
+    }
+}
@@ -0,0 +1,360 @@
+//! Low-overhead statistics of the allocations made by `mbox`.
+//!
+//! When the `alloc_stats` feature is enabled, `gen_malloc()`, `gen_realloc()`, `gen_free()` and
+//! everything built on top of them (i.e. the allocations owned by `MBox`, `MVec`, `MString` etc.)
+//! update a few global atomic counters, which can be read using `snapshot()`:
+//!
+//! ```rust
+//! extern crate mbox;
+//!
+//! use mbox::{stats, MBox};
+//!
+//! let before = stats::snapshot();
+//! let boxed = MBox::new([0_u8; 100]);
+//! let after = stats::snapshot();
+//! assert!(after.total_allocations > before.total_allocations);
+//! # drop(boxed);
+//! ```
+//!
+//! The byte counters are measured using `malloc_usable_size()`, and thus only available on Linux.
+//! They stay zero on other platforms.
+//!
+//! The live counters measure the memory currently owned by `mbox`, wherever it was allocated. A
+//! pointer adopted from C (e.g. by `MBox::from_raw()`) becomes live until it is freed or released
+//! again, and a pointer released to C (e.g. by `MBox::into_raw()`) is no longer live. The total
+//! counters and the hooks only cover the allocations actually made by `mbox`.
+//!
+//! To export the allocations to a metrics system, callbacks can be installed with
+//! `set_alloc_hook()` and `set_free_hook()`.
+
+use libc::c_void;
+
+use std::mem::transmute;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// A snapshot of the allocation counters.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Stats {
+    /// The number of allocations currently owned by `mbox`.
+    pub live_allocations: usize,
+    /// The total size in bytes of the allocations currently owned by `mbox`.
+    pub live_bytes: usize,
+    /// The total number of allocations made so far, excluding reallocations.
+    pub total_allocations: usize,
+    /// The total number of reallocations made so far.
+    pub reallocations: usize,
+    /// The maximum of `live_bytes` observed since the start or the last `reset_peak()`.
+    pub peak_bytes: usize,
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+// the live counters are the differences of these monotonic counters, so that a snapshot never
+// observes an underflow while other threads are updating them.
+static ACQUIRED: AtomicUsize = AtomicUsize::new(0);
+static RELEASED: AtomicUsize = AtomicUsize::new(0);
+static ACQUIRED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static RELEASED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// the live counters of the current thread, so that tests can check they stay balanced while other
+// tests are running.
+#[cfg(all(test, feature = "std"))]
+thread_local! {
+    static THREAD_LIVE: std::cell::Cell<(isize, isize)> = std::cell::Cell::new((0, 0));
+}
+
+static ALLOC_HOOK: AtomicPtr<()> = AtomicPtr::new(null_mut());
+static FREE_HOOK: AtomicPtr<()> = AtomicPtr::new(null_mut());
+
+/// A callback receiving the address and the size in bytes of an allocation. The size is measured
+/// the same way as the byte counters, i.e. it is always 0 outside of Linux.
+pub type Hook = fn(ptr: *mut c_void, size: usize);
+
+/// Reads the current values of the counters.
+///
+/// The counters are updated independently, so a snapshot taken while other threads are allocating
+/// may be slightly inconsistent.
+pub fn snapshot() -> Stats {
+    let released = RELEASED.load(Ordering::Relaxed);
+    let released_bytes = RELEASED_BYTES.load(Ordering::Relaxed);
+    Stats {
+        live_allocations: ACQUIRED.load(Ordering::Relaxed).saturating_sub(released),
+        live_bytes: ACQUIRED_BYTES
+            .load(Ordering::Relaxed)
+            .saturating_sub(released_bytes),
+        total_allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        reallocations: REALLOCATIONS.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Resets `peak_bytes` to the current `live_bytes`.
+pub fn reset_peak() {
+    PEAK_BYTES.store(snapshot().live_bytes, Ordering::Relaxed);
+}
+
+/// Installs a callback which is called after every allocation made by `mbox`, or removes it if
+/// `None`. A reallocation is reported as a free of the old pointer followed by an allocation of
+/// the new one.
+///
+/// The hook may be called from any thread, and must not allocate through `mbox` itself.
+pub fn set_alloc_hook(hook: Option<Hook>) {
+    ALLOC_HOOK.store(hook.map_or(null_mut(), |h| h as *mut ()), Ordering::Release);
+}
+
+/// Installs a callback which is called before every pointer is freed by `mbox`, or removes it if
+/// `None`.
+///
+/// The hook may be called from any thread, and must not allocate through `mbox` itself.
+pub fn set_free_hook(hook: Option<Hook>) {
+    FREE_HOOK.store(hook.map_or(null_mut(), |h| h as *mut ()), Ordering::Release);
+}
+
+fn call_hook(hook: &AtomicPtr<()>, ptr: *const c_void, size: usize) {
+    let hook = hook.load(Ordering::Acquire);
+    if !hook.is_null() {
+        // SAFETY: the pointer is only ever stored from a `Hook`.
+        let hook = unsafe { transmute::<*mut (), Hook>(hook) };
+        hook(ptr as *mut c_void, size);
+    }
+}
+
+/// Returns the size of the allocation at `ptr` as counted by the statistics.
+#[cfg(all(target_os = "linux", not(miri)))]
+pub(crate) fn tracked_size(ptr: *const c_void) -> usize {
+    // SAFETY: the pointer is a live allocation obtained from `malloc()`.
+    unsafe { libc::malloc_usable_size(ptr as *mut c_void) }
+}
+
+#[cfg(not(all(target_os = "linux", not(miri))))]
+pub(crate) fn tracked_size(_: *const c_void) -> usize {
+    0
+}
+
+#[cfg(all(test, feature = "std"))]
+fn track(count: isize, bytes: isize) {
+    THREAD_LIVE.with(|live| {
+        let (c, b) = live.get();
+        live.set((c + count, b + bytes));
+    });
+}
+
+#[cfg(not(all(test, feature = "std")))]
+fn track(_: isize, _: isize) {}
+
+fn acquire_bytes(size: usize) {
+    let acquired = ACQUIRED_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    let live = acquired.saturating_sub(RELEASED_BYTES.load(Ordering::Relaxed));
+    // equivalent to `fetch_max()`, which requires Rust 1.45.
+    let mut peak = PEAK_BYTES.load(Ordering::Relaxed);
+    while peak < live {
+        match PEAK_BYTES.compare_exchange_weak(peak, live, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(current) => peak = current,
+        }
+    }
+}
+
+fn release(size: usize) {
+    track(-1, -(size as isize));
+    RELEASED.fetch_add(1, Ordering::Relaxed);
+    RELEASED_BYTES.fetch_add(size, Ordering::Relaxed);
+}
+
+/// Records a new allocation made by `mbox`. It only becomes live once a box adopts it.
+pub(crate) fn record_alloc(ptr: *const c_void) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    call_hook(&ALLOC_HOOK, ptr, tracked_size(ptr));
+}
+
+/// Records a pointer being adopted by a box, whether it was allocated by `mbox` or by C.
+pub(crate) fn record_adopt(ptr: *const c_void) {
+    let size = tracked_size(ptr);
+    track(1, size as isize);
+    ACQUIRED.fetch_add(1, Ordering::Relaxed);
+    acquire_bytes(size);
+}
+
+/// Records a pointer being released from a box, while the memory itself is still alive.
+pub(crate) fn record_release(ptr: *const c_void) {
+    release(tracked_size(ptr));
+}
+
+/// Records a pointer owned by a box about to be freed.
+pub(crate) fn record_free(ptr: *const c_void) {
+    let size = tracked_size(ptr);
+    call_hook(&FREE_HOOK, ptr, size);
+    release(size);
+}
+
+/// Records an allocation owned by a box of `old_size` bytes (as returned by `tracked_size()`
+/// before the call) being moved from `old_ptr` to `new_ptr` by `realloc()`.
+pub(crate) fn record_realloc(old_ptr: *const c_void, old_size: usize, new_ptr: *const c_void) {
+    let new_size = tracked_size(new_ptr);
+    call_hook(&FREE_HOOK, old_ptr, old_size);
+    track(0, new_size as isize - old_size as isize);
+    REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    RELEASED_BYTES.fetch_add(old_size, Ordering::Relaxed);
+    acquire_bytes(new_size);
+    call_hook(&ALLOC_HOOK, new_ptr, new_size);
+}
+
+#[test]
+fn test_stats() {
+    let before = snapshot();
+    let acquired_bytes = ACQUIRED_BYTES.load(Ordering::Relaxed);
+    let mut vec = crate::MVec::<u8>::with_capacity(1 << 20);
+    let allocated = snapshot();
+    assert!(allocated.total_allocations > before.total_allocations);
+    if cfg!(all(target_os = "linux", not(miri))) {
+        // other tests may free memory concurrently, so `live_bytes` is not reliable here.
+        assert!(ACQUIRED_BYTES.load(Ordering::Relaxed) - acquired_bytes >= 1 << 20);
+    }
+
+    vec.reserve_exact(2 << 20);
+    assert!(snapshot().reallocations > allocated.reallocations);
+    drop(vec);
+
+    reset_peak();
+    let after = snapshot();
+    assert!(after.peak_bytes >= after.live_bytes);
+}
+
+/// Runs `f` with hooks recording the events of the current thread, and returns these events as
+/// `(is_alloc, ptr, size)` tuples.
+#[cfg(all(test, feature = "std"))]
+fn capture_hook_events<F: FnOnce()>(f: F) -> Vec<(bool, usize, usize)> {
+    use std::cell::RefCell;
+    use std::thread::yield_now;
+
+    // the hooks are global, so the tests installing them must not overlap.
+    static HOOKS_IN_USE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    struct Guard;
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            set_alloc_hook(None);
+            set_free_hook(None);
+            HOOKS_IN_USE.store(false, Ordering::Release);
+        }
+    }
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<(bool, usize, usize)>> = RefCell::new(Vec::new());
+    }
+
+    fn on_alloc(ptr: *mut c_void, size: usize) {
+        EVENTS.with(|e| e.borrow_mut().push((true, ptr as usize, size)));
+    }
+
+    fn on_free(ptr: *mut c_void, size: usize) {
+        EVENTS.with(|e| e.borrow_mut().push((false, ptr as usize, size)));
+    }
+
+    while HOOKS_IN_USE
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        yield_now();
+    }
+    let guard = Guard;
+    set_alloc_hook(Some(on_alloc));
+    set_free_hook(Some(on_free));
+    f();
+    drop(guard);
+
+    EVENTS.with(|e| e.replace(Vec::new()))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_hooks() {
+    let mut old_ptr = 0;
+    let mut new_ptr = 0;
+    let events = capture_hook_events(|| {
+        let mut vec = crate::MVec::<u64>::with_capacity(4);
+        old_ptr = vec.as_ptr() as usize;
+        vec.reserve_exact(100);
+        new_ptr = vec.as_ptr() as usize;
+    });
+    let ptrs = events
+        .iter()
+        .map(|&(is_alloc, ptr, _)| (is_alloc, ptr))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ptrs,
+        [
+            (true, old_ptr),
+            (false, old_ptr),
+            (true, new_ptr),
+            (false, new_ptr)
+        ]
+    );
+    if cfg!(all(target_os = "linux", not(miri))) {
+        assert!(events[0].2 >= 32);
+        assert_eq!(events[0].2, events[1].2);
+        assert!(events[2].2 >= 800);
+        assert_eq!(events[2].2, events[3].2);
+    }
+}
+
+#[cfg(all(feature = "std", feature = "global_malloc"))]
+#[test]
+fn test_hooks_into_box() {
+    let mut ptr = 0;
+    let mut live = (0, 0);
+    let events = capture_hook_events(|| {
+        let before = THREAD_LIVE.with(|live| live.get());
+        // SAFETY: `Malloc` is registered as the global allocator in the tests.
+        let boxed = unsafe { crate::MBox::into_box(crate::MBox::new(1_u64)) };
+        let after = THREAD_LIVE.with(|live| live.get());
+        live = (after.0 - before.0, after.1 - before.1);
+        ptr = &*boxed as *const u64 as usize;
+    });
+    // the allocation is handed over to `Box`, but it is never freed by `mbox`.
+    assert_eq!(
+        events
+            .iter()
+            .map(|&(is_alloc, ptr, _)| (is_alloc, ptr))
+            .collect::<Vec<_>>(),
+        [(true, ptr)]
+    );
+    assert_eq!(live, (0, 0));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_live_balanced() {
+    use crate::{MArray, MBox, MString, MVec};
+
+    let live = || THREAD_LIVE.with(|live| live.get());
+    let before = live();
+
+    // SAFETY: the pointer is allocated by `malloc()`, and ownership is passed back and forth.
+    unsafe {
+        let adopted = MBox::from_raw(libc::malloc(8) as *mut u64);
+        assert_eq!(live().0, before.0 + 1);
+        drop(adopted);
+        assert_eq!(live(), before);
+
+        let released = MBox::into_raw(MBox::new(1_u32));
+        assert_eq!(live(), before);
+        drop(MBox::from_raw(released));
+    }
+
+    assert_eq!(MBox::into_inner(MBox::new(1_u8)), 1);
+    let mut vec = MVec::with_capacity(1);
+    vec.extend(0..100_u32);
+    let mut slice = vec.into_boxed_slice();
+    slice.push(100);
+    slice.extend_from_slice(&[101, 102]);
+    assert_eq!(slice.into_iter().take(3).count(), 3);
+    let mut string = MBox::<str>::from("hello");
+    string.push_str(", world");
+    drop(string.into_bytes());
+    drop(MString::from("hello").into_bytes());
+    drop(MArray::from_slice(&[1_u8, 2, 3]).into_mbox());
+    assert_eq!(live(), before);
+}
@@ -0,0 +1,874 @@
+//! `malloc`-based growable vector.
+
+use libc::c_void;
+
+#[cfg(feature = "stable_deref_trait")]
+use stable_deref_trait::StableDeref;
+
+use std::borrow::{Borrow, BorrowMut};
+use std::cmp::Ordering;
+use std::convert::{AsMut, AsRef};
+use std::fmt::{Debug, Formatter, Result as FormatResult};
+use std::hash::{Hash, Hasher};
+use std::iter::{Extend, FromIterator, IntoIterator};
+use std::mem::{forget, size_of};
+use std::ops::{Deref, DerefMut};
+use std::ptr::{copy, copy_nonoverlapping, drop_in_place, read, write, NonNull};
+use std::slice::{from_raw_parts, from_raw_parts_mut, Iter, IterMut};
+
+#[cfg(feature = "check_pointers")]
+use std::mem::align_of;
+
+#[cfg(feature = "check_pointers")]
+use crate::internal::{check_aligned, check_non_null};
+use crate::internal::{
+    gen_free, record_adopt, record_release, try_gen_malloc, try_gen_realloc, usable_count,
+    AllocError,
+};
+use crate::mbox::{MBox, MSliceIntoIter};
+
+#[cfg(all(test, target_os = "linux", not(miri)))]
+use crate::internal::gen_usable_size;
+#[cfg(all(test, not(windows)))]
+use crate::internal::DropCounter;
+#[cfg(test)]
+use crate::internal::PanicOnClone;
+
+/// A `Vec`-like growable array backed by `malloc()`.
+///
+/// The buffer is allocated and grown by `malloc()` and `realloc()`, so it can be exchanged with C
+/// code tracking the `(ptr, len, cap)` triple without copying, using `from_raw_parts()` and
+/// `into_raw_parts_with_capacity()`.
+pub struct MVec<T> {
+    ptr: NonNull<T>,
+    cap: usize,
+    len: usize,
+}
+
+unsafe impl<T: Send> Send for MVec<T> {}
+unsafe impl<T: Sync> Sync for MVec<T> {}
+
+impl<T> MVec<T> {
+    /// Constructs a new, empty `MVec`.
+    ///
+    /// Unlike `Vec::new()`, this will still call `malloc()`, since the pointer must always be
+    /// releasable by `free()`.
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Constructs a new, empty `MVec` with at least the specified capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::try_with_capacity(capacity).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Constructs a new, empty `MVec` with at least the specified capacity. Returns an
+    /// `AllocError` if the memory cannot be allocated.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, AllocError> {
+        let ptr = try_gen_malloc(capacity)?;
+        record_adopt(ptr.as_ptr() as *const c_void);
+        Ok(Self {
+            ptr,
+            cap: capacity,
+            len: 0,
+        })
+    }
+
+    /// Constructs an `MVec` from a pointer, a length and a capacity.
+    ///
+    /// # Safety
+    ///
+    /// The `ptr` must be allocated via `malloc()`, `calloc()` or similar C functions that is
+    /// expected to be deallocated using `free()`. It must be aligned and not null. The `malloc`ed
+    /// size of the pointer must be at least `capacity * size_of::<T>()`, and the first `length`
+    /// items must already be initialized. The pointer's ownership is passed into the vector, and
+    /// thus should not be used after this function returns.
+    pub unsafe fn from_raw_parts(ptr: *mut T, length: usize, capacity: usize) -> Self {
+        #[cfg(feature = "check_pointers")]
+        {
+            check_non_null(ptr);
+            check_aligned(ptr, align_of::<T>());
+        }
+        record_adopt(ptr as *const c_void);
+        Self {
+            ptr: NonNull::new_unchecked(ptr),
+            cap: capacity,
+            len: length,
+        }
+    }
+
+    /// Decomposes the vector into a pointer to the first element, the length and the capacity.
+    ///
+    /// The caller is responsible for `free`ing the pointer after this.
+    pub fn into_raw_parts_with_capacity(self) -> (*mut T, usize, usize) {
+        let parts = (self.ptr.as_ptr(), self.len, self.cap);
+        record_release(parts.0 as *const c_void);
+        forget(self);
+        parts
+    }
+
+    /// Converts the vector into a boxed slice.
+    ///
+    /// The excess capacity is kept in the allocation, which is fine since `free()` does not need
+    /// to know the allocated size.
+    pub fn into_boxed_slice(self) -> MBox<[T]> {
+        let (ptr, len, _) = self.into_raw_parts_with_capacity();
+        // SAFETY: `ptr` has been allocated by malloc() and holds `len` initialized items, followed
+        // by the unused capacity which is freed together with them.
+        unsafe { MBox::from_raw_parts(ptr, len) }
+    }
+
+    /// Grows the capacity to cover all usable space in the allocation, as reported by
+    /// `malloc_usable_size()`, so that the slack left by `malloc()` can be filled without calling
+    /// `realloc()`.
+    ///
+    /// On platforms where the usable size cannot be queried, this has no effect.
+    pub fn adopt_usable_capacity(&mut self) {
+        // SAFETY: `ptr` is obtained from `malloc()` with room for at least `cap` items.
+        self.cap = unsafe { usable_count(self.ptr, self.cap) };
+    }
+
+    /// Returns the number of items in the vector.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the vector contains no items.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of items the vector can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        if size_of::<T>() == 0 {
+            !0
+        } else {
+            self.cap
+        }
+    }
+
+    /// Sets the length of the vector.
+    ///
+    /// # Safety
+    ///
+    /// `new_len` must be less than or equal to `capacity()`, and the items at `old_len..new_len`
+    /// must be initialized.
+    pub unsafe fn set_len(&mut self, new_len: usize) {
+        self.len = new_len;
+    }
+
+    /// Returns a raw pointer to the vector's buffer.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns a raw mutable pointer to the vector's buffer.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Extracts a slice containing the entire vector.
+    pub fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    /// Extracts a mutable slice containing the entire vector.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+
+    /// Reallocates the buffer to hold exactly `new_cap` items.
+    fn try_realloc(&mut self, new_cap: usize) -> Result<(), AllocError> {
+        // SAFETY: `ptr` is obtained from `malloc()` with room for `cap` items. The whole buffer is
+        // passed so that it is still large enough if the content is moved on error.
+        unsafe {
+            try_gen_realloc(&mut self.ptr, self.cap, new_cap)?;
+        }
+        self.cap = new_cap;
+        Ok(())
+    }
+
+    /// Reserves capacity for at least `additional` more items. The capacity is grown
+    /// exponentially to amortize the cost of `realloc()`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.try_reserve(additional).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Reserves capacity for at least `additional` more items. Returns an `AllocError` if the
+    /// memory cannot be allocated, in which case the vector is left unchanged.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        if additional <= self.capacity() - self.len {
+            return Ok(());
+        }
+        let required = self
+            .len
+            .checked_add(additional)
+            .ok_or_else(AllocError::capacity_overflow)?;
+        let new_cap = required.max(self.cap.saturating_mul(2)).max(4);
+        self.try_realloc(new_cap)
+    }
+
+    /// Reserves capacity for exactly `additional` more items.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.try_reserve_exact(additional)
+            .unwrap_or_else(|e| e.handle())
+    }
+
+    /// Reserves capacity for exactly `additional` more items. Returns an `AllocError` if the
+    /// memory cannot be allocated, in which case the vector is left unchanged.
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), AllocError> {
+        if additional <= self.capacity() - self.len {
+            return Ok(());
+        }
+        let required = self
+            .len
+            .checked_add(additional)
+            .ok_or_else(AllocError::capacity_overflow)?;
+        self.try_realloc(required)
+    }
+
+    /// Shrinks the capacity of the vector to its length using `realloc()`.
+    pub fn shrink_to_fit(&mut self) {
+        self.try_shrink_to_fit().unwrap_or_else(|e| e.handle())
+    }
+
+    /// Shrinks the capacity of the vector to its length using `realloc()`. Returns an `AllocError`
+    /// if the memory cannot be reallocated, in which case the vector is left unchanged.
+    pub fn try_shrink_to_fit(&mut self) -> Result<(), AllocError> {
+        if self.capacity() > self.len {
+            self.try_realloc(self.len)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Appends an item to the end of the vector.
+    pub fn push(&mut self, value: T) {
+        self.try_push(value).unwrap_or_else(|e| e.handle())
+    }
+
+    /// Appends an item to the end of the vector. Returns an `AllocError` if the vector is full and
+    /// cannot be grown. The vector is left unchanged on error.
+    pub fn try_push(&mut self, value: T) -> Result<(), AllocError> {
+        self.try_reserve(1)?;
+        // SAFETY: the capacity reserved above ensures `ptr[len]` is valid but uninitialized.
+        unsafe {
+            write(self.ptr.as_ptr().add(self.len), value);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the last item from the vector and returns it, or `None` if it is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+            // SAFETY: `ptr[len]` was initialized, and is now outside of the vector.
+            unsafe { Some(read(self.ptr.as_ptr().add(self.len))) }
+        }
+    }
+
+    /// Inserts an item at position `index`, shifting all items after it to the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        let len = self.len;
+        assert!(
+            index <= len,
+            "insertion index (is {}) should be <= len (is {})",
+            index,
+            len
+        );
+        self.reserve(1);
+        // SAFETY: the capacity reserved above ensures there is room for `len + 1` items.
+        unsafe {
+            let p = self.ptr.as_ptr().add(index);
+            copy(p, p.add(1), len - index);
+            write(p, value);
+        }
+        self.len += 1;
+    }
+
+    /// Removes and returns the item at position `index`, shifting all items after it to the
+    /// left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        let len = self.len;
+        assert!(
+            index < len,
+            "removal index (is {}) should be < len (is {})",
+            index,
+            len
+        );
+        // SAFETY: `ptr[index]` is initialized, and is moved out before the tail overwrites it.
+        unsafe {
+            let p = self.ptr.as_ptr().add(index);
+            let value = read(p);
+            copy(p.add(1), p, len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    /// Shortens the vector, keeping the first `len` items and dropping the rest. The capacity is
+    /// not changed.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            let tail_len = self.len - len;
+            // set `len` first, so the items are leaked instead of double-dropped if a destructor
+            // panics.
+            self.len = len;
+            // SAFETY: the items in the tail are initialized and now outside of the vector.
+            unsafe {
+                let tail = from_raw_parts_mut(self.ptr.as_ptr().add(len), tail_len);
+                drop_in_place(tail);
+            }
+        }
+    }
+
+    /// Removes all items from the vector. The capacity is not changed.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Retains only the items specified by the predicate, preserving their order.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        /// Moves the unvisited items over the holes left by the deleted items and restores the
+        /// length when dropped, so that nothing is leaked or double-dropped even if `f` or a
+        /// destructor panics.
+        struct Compactor<'a, T> {
+            vec: &'a mut MVec<T>,
+            len: usize,
+            processed: usize,
+            deleted: usize,
+        }
+
+        impl<T> Drop for Compactor<'_, T> {
+            fn drop(&mut self) {
+                // SAFETY: the items in `processed..len` are not visited yet, and are moved as a
+                // whole to follow the retained items.
+                unsafe {
+                    if self.deleted > 0 {
+                        let src = self.vec.ptr.as_ptr().add(self.processed);
+                        copy(src, src.sub(self.deleted), self.len - self.processed);
+                    }
+                }
+                self.vec.len = self.len - self.deleted;
+            }
+        }
+
+        let len = self.len;
+        // set `len` to 0 first, so the items are leaked instead of double-dropped if the
+        // compactor itself is leaked.
+        self.len = 0;
+        let mut compactor = Compactor {
+            vec: self,
+            len,
+            processed: 0,
+            deleted: 0,
+        };
+        // SAFETY: every item is visited exactly once, and is either dropped or moved towards the
+        // front of the buffer to fill the holes left by the deleted items. An item is counted as
+        // processed before it is dropped, so a panicking destructor does not drop it again.
+        unsafe {
+            while compactor.processed < len {
+                let cur = compactor.vec.ptr.as_ptr().add(compactor.processed);
+                if !f(&*cur) {
+                    compactor.processed += 1;
+                    compactor.deleted += 1;
+                    drop_in_place(cur);
+                } else {
+                    if compactor.deleted > 0 {
+                        copy(cur, cur.sub(compactor.deleted), 1);
+                    }
+                    compactor.processed += 1;
+                }
+            }
+        }
+    }
+}
+
+impl<T: Clone> MVec<T> {
+    /// Appends clones of all items in `other` to the end of the vector.
+    ///
+    /// With the `nightly` feature, this is a single `memcpy()` when `T` is a primitive type or a
+    /// raw pointer. Use `extend_from_copy_slice()` to get the same on stable.
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        self.reserve(other.len());
+        T::spec_extend_from_slice(self, other);
+    }
+}
+
+impl<T: BitCopy> MVec<T> {
+    /// Appends copies of all items in `other` to the end of the vector using a single `memcpy()`.
+    pub fn extend_from_copy_slice(&mut self, other: &[T]) {
+        self.reserve(other.len());
+        copy_into_reserved(self, other);
+    }
+}
+
+/// Marker for types which can be cloned by copying their bits, allowing slices of them to be
+/// cloned with a single `memcpy()`.
+///
+/// This is implemented for the primitive types and raw pointers. The `*_copy_slice` methods (e.g.
+/// `MVec::extend_from_copy_slice()`) accept every type implementing this trait. With the
+/// `nightly` feature, `extend_from_slice()`, `from_slice()` and `clone()` of slices also use
+/// `memcpy()` for the built-in implementations.
+///
+/// # Safety
+///
+/// The `Clone` implementation of the type must be equivalent to a bitwise copy, which is the case
+/// for `#[derive(Clone, Copy)]`.
+pub unsafe trait BitCopy: Copy {}
+
+/// Implementation of `MVec::extend_from_slice()`, specialized for `BitCopy` types on nightly.
+trait SpecExtendFromSlice: Clone {
+    /// Clones `other` into the items after `vec.len`. The capacity must have been reserved.
+    fn spec_extend_from_slice(vec: &mut MVec<Self>, other: &[Self]);
+}
+
+impl<T: Clone> SpecExtendFromSlice for T {
+    #[cfg(feature = "nightly")]
+    default fn spec_extend_from_slice(vec: &mut MVec<Self>, other: &[Self]) {
+        clone_into_reserved(vec, other);
+    }
+
+    #[cfg(not(feature = "nightly"))]
+    fn spec_extend_from_slice(vec: &mut MVec<Self>, other: &[Self]) {
+        clone_into_reserved(vec, other);
+    }
+}
+
+macro_rules! impl_bit_copy {
+    ($([$($generics:tt)*] $ty:ty),* $(,)?) => {$(
+        unsafe impl<$($generics)*> BitCopy for $ty {}
+
+        #[cfg(feature = "nightly")]
+        impl<$($generics)*> SpecExtendFromSlice for $ty {
+            fn spec_extend_from_slice(vec: &mut MVec<Self>, other: &[Self]) {
+                copy_into_reserved(vec, other);
+            }
+        }
+    )*};
+}
+
+impl_bit_copy! {
+    [] u8, [] u16, [] u32, [] u64, [] u128, [] usize,
+    [] i8, [] i16, [] i32, [] i64, [] i128, [] isize,
+    [] f32, [] f64, [] bool, [] char, [] (),
+    [T: ?Sized] *const T, [T: ?Sized] *mut T,
+}
+
+/// Copies `other` into the items after `vec.len` using a single `memcpy()`. The capacity must have
+/// been reserved.
+fn copy_into_reserved<T: BitCopy>(vec: &mut MVec<T>, other: &[T]) {
+    // SAFETY: cloning a `BitCopy` type is the same as a bitwise copy. The capacity has been
+    // reserved by the caller.
+    unsafe {
+        copy_nonoverlapping(other.as_ptr(), vec.ptr.as_ptr().add(vec.len), other.len());
+    }
+    vec.len += other.len();
+}
+
+/// Clones `other` item by item into the items after `vec.len`. The capacity must have been
+/// reserved.
+fn clone_into_reserved<T: Clone>(vec: &mut MVec<T>, other: &[T]) {
+    for item in other {
+        // SAFETY: the capacity has been reserved by the caller. `len` is increased after every
+        // item is initialized, so the vector remains valid even if `clone()` panics.
+        unsafe {
+            write(vec.ptr.as_ptr().add(vec.len), item.clone());
+        }
+        vec.len += 1;
+    }
+}
+
+impl<T> Drop for MVec<T> {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` has been allocated by `malloc()`, and the first `len` items have been
+        // initialized.
+        unsafe {
+            drop_in_place(from_raw_parts_mut(self.ptr.as_ptr(), self.len));
+            gen_free(self.ptr);
+        }
+    }
+}
+
+impl<T> Deref for MVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for MVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl<T> StableDeref for MVec<T> {}
+
+impl<T> AsRef<[T]> for MVec<T> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T> AsMut<[T]> for MVec<T> {
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T> Borrow<[T]> for MVec<T> {
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T> BorrowMut<[T]> for MVec<T> {
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T> Default for MVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Clone for MVec<T> {
+    fn clone(&self) -> Self {
+        let mut vec = Self::with_capacity(self.len);
+        vec.extend_from_slice(self);
+        vec
+    }
+}
+
+impl<T: Debug> Debug for MVec<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FormatResult {
+        self.deref().fmt(formatter)
+    }
+}
+
+impl<T: Hash> Hash for MVec<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state)
+    }
+}
+
+impl<U, T: PartialEq<U>> PartialEq<MVec<U>> for MVec<T> {
+    fn eq(&self, other: &MVec<U>) -> bool {
+        self.deref().eq(other.deref())
+    }
+}
+
+impl<T: Eq> Eq for MVec<T> {}
+
+impl<T: PartialOrd> PartialOrd for MVec<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.deref().partial_cmp(other.deref())
+    }
+}
+
+impl<T: Ord> Ord for MVec<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.deref().cmp(other.deref())
+    }
+}
+
+impl<T> Extend<T> for MVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a> Extend<&'a T> for MVec<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned());
+    }
+}
+
+impl<T> FromIterator<T> for MVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = Self::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T> IntoIterator for MVec<T> {
+    type Item = T;
+    type IntoIter = MSliceIntoIter<T>;
+    fn into_iter(self) -> MSliceIntoIter<T> {
+        self.into_boxed_slice().into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a MVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut MVec<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+impl<T> From<MVec<T>> for MBox<[T]> {
+    fn from(vec: MVec<T>) -> Self {
+        vec.into_boxed_slice()
+    }
+}
+
+impl<T> From<MBox<[T]>> for MVec<T> {
+    /// Converts a boxed slice into a vector without reallocating. The capacity of the resulting
+    /// vector is the same as its length.
+    fn from(slice: MBox<[T]>) -> Self {
+        let (ptr, len) = slice.into_raw_parts();
+        // SAFETY: `ptr` is allocated by malloc() with at least `len` items.
+        unsafe { Self::from_raw_parts(ptr, len, len) }
+    }
+}
+
+impl<T: Clone> From<&[T]> for MVec<T> {
+    fn from(slice: &[T]) -> Self {
+        let mut vec = Self::with_capacity(slice.len());
+        vec.extend_from_slice(slice);
+        vec
+    }
+}
+
+#[test]
+fn test_push_pop() {
+    let mut vec = MVec::new();
+    assert!(vec.is_empty());
+    for i in 0..100u8 {
+        vec.push(i);
+    }
+    assert_eq!(vec.len(), 100);
+    assert!(vec.capacity() >= 100);
+    assert_eq!(vec[37], 37);
+    assert_eq!(vec.pop(), Some(99));
+    assert_eq!(vec.len(), 99);
+
+    vec.clear();
+    assert_eq!(vec.pop(), None);
+}
+
+#[test]
+fn test_insert_remove() {
+    let mut vec = MVec::from(&b"acd"[..]);
+    vec.insert(1, b'b');
+    vec.insert(4, b'e');
+    assert_eq!(&*vec, b"abcde");
+    assert_eq!(vec.remove(0), b'a');
+    assert_eq!(vec.remove(3), b'e');
+    assert_eq!(&*vec, b"bcd");
+}
+
+#[test]
+#[should_panic(expected = "insertion index (is 4) should be <= len (is 3)")]
+fn test_insert_out_of_bounds() {
+    let mut vec = MVec::from(&b"abc"[..]);
+    vec.insert(4, b'd');
+}
+
+#[test]
+fn test_reserve_and_shrink() {
+    let mut vec = MVec::<u8>::with_capacity(2);
+    assert_eq!(vec.capacity(), 2);
+    vec.reserve(10);
+    assert!(vec.capacity() >= 10);
+    vec.reserve_exact(100);
+    assert_eq!(vec.capacity(), 100);
+    vec.extend(b"xyz");
+    vec.shrink_to_fit();
+    assert_eq!(vec.capacity(), 3);
+    assert_eq!(&*vec, b"xyz");
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_reserve_over_aligned() {
+    #[repr(C, align(64))]
+    struct A(u64);
+
+    let mut vec = MVec::new();
+    for i in 0..1000 {
+        vec.push(A(i));
+        assert_eq!(vec.as_ptr() as usize % 64, 0);
+    }
+    vec.truncate(10);
+    vec.shrink_to_fit();
+    assert_eq!(vec.as_ptr() as usize % 64, 0);
+    assert!(vec.iter().map(|a| a.0).eq(0..10));
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+#[test]
+fn test_adopt_usable_capacity() {
+    let mut vec = MVec::<u8>::with_capacity(5);
+    vec.extend(b"hello");
+    vec.adopt_usable_capacity();
+    let cap = vec.capacity();
+    assert!(cap >= 5);
+    assert_eq!(cap, unsafe { gen_usable_size(vec.ptr) });
+
+    let ptr = vec.as_ptr();
+    while vec.len() < cap {
+        vec.push(b'!');
+    }
+    assert_eq!(vec.as_ptr(), ptr);
+    assert_eq!(&vec[..5], b"hello");
+}
+
+#[test]
+fn test_extend_from_copy_slice() {
+    let bytes = (0..1 << 20).map(|i| i as u8).collect::<MVec<u8>>();
+    let mut vec = MVec::from(&bytes[..10]);
+    vec.extend_from_slice(&bytes);
+    assert_eq!(vec.len(), 10 + (1 << 20));
+    assert_eq!(&vec[..10], &bytes[..10]);
+    assert_eq!(&vec[10..], &*bytes);
+    assert_eq!(
+        MBox::from_slice(&bytes).clone(),
+        bytes.clone().into_boxed_slice()
+    );
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct Pixel(u8, u8, u8);
+    unsafe impl BitCopy for Pixel {}
+
+    let pixels = [Pixel(1, 2, 3), Pixel(4, 5, 6)];
+    let mut vec = MVec::new();
+    vec.extend_from_copy_slice(&pixels);
+    vec.extend_from_copy_slice(&pixels[1..]);
+    assert_eq!(&*vec, &[Pixel(1, 2, 3), Pixel(4, 5, 6), Pixel(4, 5, 6)]);
+    assert_eq!(&*MBox::from_copy_slice(&pixels), &pixels);
+    assert_eq!(&*MBox::from_copy_slice(&bytes[..3]), &[0, 1, 2]);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_extend_from_clone_slice() {
+    let counter = DropCounter::default();
+    {
+        let mut vec = MVec::new();
+        vec.extend_from_slice(&[counter.clone(), counter.clone()]);
+        counter.assert_eq(2);
+        assert_eq!(vec.len(), 2);
+    }
+    counter.assert_eq(4);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_retain() {
+    let counter = DropCounter::default();
+    {
+        let mut vec = (0..10u64)
+            .map(|i| (i, counter.clone()))
+            .collect::<MVec<_>>();
+        vec.retain(|&(i, _)| i % 3 == 0);
+        counter.assert_eq(6);
+        assert_eq!(vec.len(), 4);
+        assert!(vec.iter().map(|&(i, _)| i).eq([0, 3, 6, 9].iter().copied()));
+    }
+    counter.assert_eq(10);
+}
+
+#[cfg(all(feature = "std", not(windows)))]
+#[test]
+fn test_retain_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let counter = DropCounter::default();
+    let mut vec = (0..10u64)
+        .map(|i| (i, counter.clone()))
+        .collect::<MVec<_>>();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        vec.retain(|&(i, _)| {
+            assert!(i < 5, "panic in retain");
+            i % 2 == 0
+        })
+    }));
+    assert!(result.is_err());
+    counter.assert_eq(2);
+    assert!(vec
+        .iter()
+        .map(|&(i, _)| i)
+        .eq([0, 2, 4, 5, 6, 7, 8, 9].iter().copied()));
+    drop(vec);
+    counter.assert_eq(10);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_into_boxed_slice() {
+    let counter = DropCounter::default();
+    {
+        let mut vec = MVec::with_capacity(8);
+        vec.push(counter.clone());
+        vec.push(counter.clone());
+        let slice: MBox<[_]> = vec.into();
+        counter.assert_eq(0);
+        assert_eq!(slice.len(), 2);
+
+        let vec = MVec::from(slice);
+        assert_eq!(vec.capacity(), 2);
+        counter.assert_eq(0);
+    }
+    counter.assert_eq(2);
+}
+
+#[test]
+fn test_raw_parts_with_capacity() {
+    let mut vec = MVec::<u8>::with_capacity(16);
+    vec.extend(b"hello");
+    let (ptr, len, cap) = vec.into_raw_parts_with_capacity();
+    assert_eq!((len, cap), (5, 16));
+
+    let mut vec = unsafe { MVec::from_raw_parts(ptr, len, cap) };
+    vec.extend(b" world");
+    assert_eq!(&*vec, b"hello world");
+}
+
+#[test]
+fn test_zst_vec() {
+    let mut vec = MVec::new();
+    for _ in 0..10 {
+        vec.push(());
+    }
+    assert_eq!(vec.len(), 10);
+    assert_eq!(vec.capacity(), !0);
+    assert_eq!(vec.into_iter().count(), 10);
+}
+
+#[test]
+#[should_panic(expected = "panic on clone")]
+fn test_panic_during_clone() {
+    let mut vec = MVec::new();
+    vec.push(PanicOnClone::default());
+    let _ = vec.clone();
+}